@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{Rng, SeedableRng};
+
+use gstdatavideo::encoding::{
+    convert_to_sdi_safe_payload_into, sdi_safe_payload_len, stuffing,
+    try_convert_from_sdi_safe_payload, FrameDecoder, FrameEncoder, FrameHeader, FrameLayout,
+    PayloadCoding, Record,
+};
+
+// A 2160p UYVY frame, at 60 fps that is just under 1 GB/s
+const UHD_FRAME_SIZE: usize = 3840 * 2160 * 2;
+
+fn random_payload(len: usize) -> Vec<u8> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn escaping(c: &mut Criterion) {
+    let mut group = c.benchmark_group("escaping");
+
+    let payload = random_payload(UHD_FRAME_SIZE / 2);
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    let mut output = vec![0; 2 * payload.len()];
+    let escaped_len = convert_to_sdi_safe_payload_into(&payload, &mut output);
+
+    group.bench_function("len", |b| {
+        b.iter(|| sdi_safe_payload_len(black_box(&payload)))
+    });
+    group.bench_function("escape", |b| {
+        b.iter(|| convert_to_sdi_safe_payload_into(black_box(&payload), &mut output))
+    });
+    group.bench_function("unescape", |b| {
+        b.iter(|| try_convert_from_sdi_safe_payload(black_box(&output[..escaped_len])))
+    });
+
+    group.finish();
+}
+
+fn stuffing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stuffing");
+
+    let payload = random_payload(UHD_FRAME_SIZE * 9 / 10);
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    let mut output = vec![0; stuffing::max_stuffed_len(payload.len())];
+    let stuffed_len = stuffing::stuff_payload_into(&payload, &mut output);
+
+    group.bench_function("stuff", |b| {
+        b.iter(|| stuffing::stuff_payload_into(black_box(&payload), &mut output))
+    });
+    group.bench_function("unstuff", |b| {
+        b.iter(|| stuffing::unstuff_payload(black_box(&output[..stuffed_len])))
+    });
+
+    group.finish();
+}
+
+/// A frame filled with a single record, from packing to unpacking
+fn frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frames");
+    group.throughput(Throughput::Bytes(UHD_FRAME_SIZE as u64));
+
+    let layout = FrameLayout::new(UHD_FRAME_SIZE);
+    let mut frame = vec![0; UHD_FRAME_SIZE];
+
+    for &coding in &[PayloadCoding::Escaped, PayloadCoding::Stuffed] {
+        let payload = random_payload(coding.max_payload_size(UHD_FRAME_SIZE));
+        let record = Record {
+            channel: 0,
+            flags: 0,
+            data: &payload,
+        };
+
+        group.bench_with_input(
+            BenchmarkId::new("encode", format!("{:?}", coding)),
+            &record,
+            |b, record| {
+                b.iter(|| {
+                    let mut encoder =
+                        FrameEncoder::with_coding(layout, FrameHeader::default(), coding);
+                    assert!(encoder.push(record));
+                    encoder.encode(&mut frame).unwrap();
+                })
+            },
+        );
+
+        let decoder = FrameDecoder::new(layout);
+        group.bench_function(BenchmarkId::new("decode", format!("{:?}", coding)), |b| {
+            b.iter(|| {
+                decoder
+                    .decode(black_box(&frame))
+                    .unwrap()
+                    .unwrap()
+                    .records()
+                    .len()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, escaping, stuffing, frames);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+use crate::encoding::captions::CaptionType;
+
+/// `None` for `VideoCaptionType::Unknown`
+pub fn from_video_caption_type(caption_type: gst_video::VideoCaptionType) -> Option<CaptionType> {
+    match caption_type {
+        gst_video::VideoCaptionType::Cea608Raw => Some(CaptionType::Cea608Raw),
+        gst_video::VideoCaptionType::Cea608S3341a => Some(CaptionType::Cea608S3341a),
+        gst_video::VideoCaptionType::Cea708Raw => Some(CaptionType::Cea708Raw),
+        gst_video::VideoCaptionType::Cea708Cdp => Some(CaptionType::Cea708Cdp),
+        _ => None,
+    }
+}
+
+pub fn to_video_caption_type(caption_type: CaptionType) -> gst_video::VideoCaptionType {
+    match caption_type {
+        CaptionType::Cea608Raw => gst_video::VideoCaptionType::Cea608Raw,
+        CaptionType::Cea608S3341a => gst_video::VideoCaptionType::Cea608S3341a,
+        CaptionType::Cea708Raw => gst_video::VideoCaptionType::Cea708Raw,
+        CaptionType::Cea708Cdp => gst_video::VideoCaptionType::Cea708Cdp,
+    }
+}
+
+/// Caps of a stream of `caption_type`, as `cccombiner` takes them
+pub fn caption_caps(caption_type: CaptionType) -> gst::Caps {
+    to_video_caption_type(caption_type).to_caps()
+}
+
+/// Caption streams, and video with `VideoCaptionMeta`
+pub fn caption_sink_caps() -> gst::Caps {
+    let mut caps = gst::Caps::new_simple("video/x-raw", &[]);
+    caps.get_mut().unwrap().append(caption_src_caps());
+    caps
+}
+
+/// Any of the caption streams `caption_caps` makes
+pub fn caption_src_caps() -> gst::Caps {
+    let mut caps = gst::Caps::new_empty();
+    {
+        let caps = caps.get_mut().unwrap();
+        for &caption_type in &[
+            CaptionType::Cea608Raw,
+            CaptionType::Cea608S3341a,
+            CaptionType::Cea708Raw,
+            CaptionType::Cea708Cdp,
+        ] {
+            caps.append(caption_caps(caption_type));
+        }
+    }
+    caps
+}
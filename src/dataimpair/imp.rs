@@ -0,0 +1,953 @@
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_info, gst_log};
+use gst_base::subclass::prelude::*;
+
+use std::i32;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::encoding::v210;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "dataimpair",
+        gst::DebugColorFlags::empty(),
+        Some("Impair video the way links and converters do"),
+    )
+});
+
+const DEFAULT_SEED: u64 = 0;
+const DEFAULT_BIT_ERROR_RATE: f64 = 0.0;
+const DEFAULT_BURST_PROBABILITY: f64 = 0.0;
+const DEFAULT_BURST_LENGTH: u32 = 64;
+const DEFAULT_LINE_BLANK_PROBABILITY: f64 = 0.0;
+const DEFAULT_DROP_PROBABILITY: f64 = 0.0;
+const DEFAULT_REPEAT_PROBABILITY: f64 = 0.0;
+const DEFAULT_GAIN: f64 = 1.0;
+const DEFAULT_OFFSET: f64 = 0.0;
+const DEFAULT_CHROMA_SUBSAMPLE: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    seed: u64,
+    bit_error_rate: f64,
+    burst_probability: f64,
+    burst_length: u32,
+    line_blank_probability: f64,
+    drop_probability: f64,
+    repeat_probability: f64,
+    gain: f64,
+    offset: f64,
+    chroma_subsample: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            seed: DEFAULT_SEED,
+            bit_error_rate: DEFAULT_BIT_ERROR_RATE,
+            burst_probability: DEFAULT_BURST_PROBABILITY,
+            burst_length: DEFAULT_BURST_LENGTH,
+            line_blank_probability: DEFAULT_LINE_BLANK_PROBABILITY,
+            drop_probability: DEFAULT_DROP_PROBABILITY,
+            repeat_probability: DEFAULT_REPEAT_PROBABILITY,
+            gain: DEFAULT_GAIN,
+            offset: DEFAULT_OFFSET,
+            chroma_subsample: DEFAULT_CHROMA_SUBSAMPLE,
+        }
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    rng: ChaCha8Rng,
+    /// Frame of the negotiated format with every line blanked
+    black: Vec<u8>,
+    /// Last frame sent on, for `repeat-probability`
+    previous: Option<Vec<u8>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            rng: ChaCha8Rng::seed_from_u64(DEFAULT_SEED),
+            black: Vec::new(),
+            previous: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DataImpair {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+/// Samples of `component` across and down, after subsampling
+fn component_size(info: &gst_video::VideoInfo, component: usize) -> (usize, usize) {
+    let format_info = info.format_info();
+    let w_sub = format_info.w_sub()[component];
+    let h_sub = format_info.h_sub()[component];
+
+    (
+        (info.width() as usize + (1 << w_sub) - 1) >> w_sub,
+        (info.height() as usize + (1 << h_sub) - 1) >> h_sub,
+    )
+}
+
+/// Byte of sample `x`, `y` of `component`, counted in samples of the component
+fn sample_offset(info: &gst_video::VideoInfo, component: usize, x: usize, y: usize) -> usize {
+    let format_info = info.format_info();
+    let plane = format_info.plane()[component] as usize;
+
+    info.offset()[plane]
+        + y * info.stride()[plane] as usize
+        + x * format_info.pixel_stride()[component] as usize
+        + format_info.poffset()[component] as usize
+}
+
+/// Calls `f` with the byte of every sample of `component`
+fn for_each_sample(info: &gst_video::VideoInfo, component: usize, mut f: impl FnMut(usize)) {
+    let (width, height) = component_size(info, component);
+    for y in 0..height {
+        for x in 0..width {
+            f(sample_offset(info, component, x, y));
+        }
+    }
+}
+
+/// Component of an 8 bit format that is alpha, if any
+fn alpha_component(format_info: &gst_video::VideoFormatInfo) -> Option<usize> {
+    format_info
+        .has_alpha()
+        .then(|| format_info.n_components() as usize - 1)
+}
+
+/// Frame of `info` that is black all over, with opaque alpha
+fn black_frame(info: &gst_video::VideoInfo) -> Vec<u8> {
+    let mut frame = vec![0; info.size()];
+
+    if info.format() == gst_video::VideoFormat::V210 {
+        let stride = info.stride()[0] as usize;
+        for line in frame.chunks_mut(stride).take(info.height() as usize) {
+            v210::write_luma(line, info.width() as usize, &[]);
+        }
+        return frame;
+    }
+
+    let format_info = info.format_info();
+    let alpha = alpha_component(&format_info);
+    for component in 0..format_info.n_components() as usize {
+        let value = if Some(component) == alpha {
+            0xff
+        } else if !format_info.is_yuv() {
+            0
+        } else if component == 0 {
+            16
+        } else {
+            128
+        };
+        for_each_sample(info, component, |offset| frame[offset] = value);
+    }
+
+    frame
+}
+
+/// Copies line `y` of `black` into `frame`, in every plane. Lines of subsampled
+/// planes are shared with their neighbours and blank them too.
+fn blank_line(info: &gst_video::VideoInfo, black: &[u8], frame: &mut [u8], y: usize) {
+    let format_info = info.format_info();
+    let mut blanked = [false; gst_video::ffi::GST_VIDEO_MAX_PLANES as usize];
+    for component in 0..format_info.n_components() as usize {
+        let plane = format_info.plane()[component] as usize;
+        if blanked[plane] {
+            continue;
+        }
+        blanked[plane] = true;
+
+        let stride = info.stride()[plane] as usize;
+        let start = info.offset()[plane] + (y >> format_info.h_sub()[component]) * stride;
+        let end = (start + stride).min(frame.len());
+        frame[start..end].copy_from_slice(&black[start..end]);
+    }
+}
+
+/// Scales and shifts luma and RGB of an 8 bit frame, chroma is only scaled around
+/// its middle
+fn apply_gain(info: &gst_video::VideoInfo, frame: &mut [u8], gain: f64, offset: f64) {
+    let format_info = info.format_info();
+    let alpha = alpha_component(&format_info);
+    for component in 0..format_info.n_components() as usize {
+        if Some(component) == alpha {
+            continue;
+        }
+
+        let chroma = format_info.is_yuv() && component != 0;
+        for_each_sample(info, component, |i| {
+            let sample = frame[i] as f64;
+            let value = if chroma {
+                128.0 + (sample - 128.0) * gain
+            } else {
+                sample * gain + offset
+            };
+            frame[i] = value.round().clamp(0.0, 255.0) as u8;
+        });
+    }
+}
+
+/// Averages the chroma of every 2x2 pixels of an 8 bit frame, as going through
+/// 4:2:0 does. RGB is converted to BT.709 YCbCr and back for that.
+fn subsample_chroma(info: &gst_video::VideoInfo, frame: &mut [u8]) {
+    let format_info = info.format_info();
+
+    if format_info.is_yuv() {
+        for component in 1..3 {
+            let (width, height) = component_size(info, component);
+            let mut average = |a: usize, b: usize| {
+                let value = (frame[a] as u16 + frame[b] as u16 + 1) / 2;
+                frame[a] = value as u8;
+                frame[b] = value as u8;
+            };
+            if format_info.h_sub()[component] == 0 {
+                for y in (0..height.saturating_sub(1)).step_by(2) {
+                    for x in 0..width {
+                        average(
+                            sample_offset(info, component, x, y),
+                            sample_offset(info, component, x, y + 1),
+                        );
+                    }
+                }
+            }
+            if format_info.w_sub()[component] == 0 {
+                for y in 0..height {
+                    for x in (0..width.saturating_sub(1)).step_by(2) {
+                        average(
+                            sample_offset(info, component, x, y),
+                            sample_offset(info, component, x + 1, y),
+                        );
+                    }
+                }
+            }
+        }
+    } else if format_info.is_rgb() {
+        let (width, height) = (info.width() as usize, info.height() as usize);
+        for top in (0..height).step_by(2) {
+            for left in (0..width).step_by(2) {
+                let pixels: Vec<[usize; 3]> = (top..(top + 2).min(height))
+                    .flat_map(|y| (left..(left + 2).min(width)).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        [
+                            sample_offset(info, 0, x, y),
+                            sample_offset(info, 1, x, y),
+                            sample_offset(info, 2, x, y),
+                        ]
+                    })
+                    .collect();
+
+                let ycbcr: Vec<(f64, f64, f64)> = pixels
+                    .iter()
+                    .map(|&[r, g, b]| {
+                        let (r, g, b) = (frame[r] as f64, frame[g] as f64, frame[b] as f64);
+                        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                        (y, (b - y) / 1.8556, (r - y) / 1.5748)
+                    })
+                    .collect();
+                let n = ycbcr.len() as f64;
+                let cb = ycbcr.iter().map(|&(_, cb, _)| cb).sum::<f64>() / n;
+                let cr = ycbcr.iter().map(|&(_, _, cr)| cr).sum::<f64>() / n;
+
+                for (&[r_offset, g_offset, b_offset], &(y, _, _)) in pixels.iter().zip(&ycbcr) {
+                    let r = y + 1.5748 * cr;
+                    let b = y + 1.8556 * cb;
+                    let g = (y - 0.2126 * r - 0.0722 * b) / 0.7152;
+                    frame[r_offset] = r.round().clamp(0.0, 255.0) as u8;
+                    frame[g_offset] = g.round().clamp(0.0, 255.0) as u8;
+                    frame[b_offset] = b.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Flips every bit of `frame` with probability `rate`, returns how many were
+/// flipped. Gaps between errors are drawn instead of a number for every bit.
+fn flip_bits(rng: &mut ChaCha8Rng, frame: &mut [u8], rate: f64) -> usize {
+    if rate <= 0.0 {
+        return 0;
+    }
+
+    let bits = frame.len() * 8;
+    let mut bit = 0;
+    let mut flipped = 0;
+    loop {
+        let gap = if rate >= 1.0 {
+            0.0
+        } else {
+            (1.0 - rng.gen::<f64>()).ln() / (1.0 - rate).ln()
+        };
+        if gap.is_nan() || gap >= (bits - bit) as f64 {
+            break;
+        }
+
+        bit += gap as usize;
+        frame[bit / 8] ^= 0x80 >> (bit % 8);
+        bit += 1;
+        flipped += 1;
+    }
+
+    flipped
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DataImpair {
+    const NAME: &'static str = "DataImpair";
+    type Type = super::DataImpair;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ObjectImpl for DataImpair {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpec::new_uint64(
+                    "seed",
+                    "Seed",
+                    "Seed of the random numbers, the same seed impairs the same frames the same way",
+                    0,
+                    u64::MAX,
+                    DEFAULT_SEED,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_double(
+                    "bit-error-rate",
+                    "Bit Error Rate",
+                    "Probability of every bit of a frame to be flipped",
+                    0.0,
+                    1.0,
+                    DEFAULT_BIT_ERROR_RATE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "burst-probability",
+                    "Burst Probability",
+                    "Probability of a frame to get a burst of random bytes",
+                    0.0,
+                    1.0,
+                    DEFAULT_BURST_PROBABILITY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "burst-length",
+                    "Burst Length",
+                    "Bytes of a burst",
+                    0,
+                    u32::MAX,
+                    DEFAULT_BURST_LENGTH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "line-blank-probability",
+                    "Line Blank Probability",
+                    "Probability of every line to be blanked",
+                    0.0,
+                    1.0,
+                    DEFAULT_LINE_BLANK_PROBABILITY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "drop-probability",
+                    "Drop Probability",
+                    "Probability of a frame to be dropped",
+                    0.0,
+                    1.0,
+                    DEFAULT_DROP_PROBABILITY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "repeat-probability",
+                    "Repeat Probability",
+                    "Probability of a frame to be replaced by the one before it",
+                    0.0,
+                    1.0,
+                    DEFAULT_REPEAT_PROBABILITY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "gain",
+                    "Gain",
+                    "Factor luma, RGB and chroma around its middle are scaled by (8 bit formats)",
+                    0.0,
+                    16.0,
+                    DEFAULT_GAIN,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_double(
+                    "offset",
+                    "Offset",
+                    "Added to luma and RGB after the gain (8 bit formats)",
+                    -255.0,
+                    255.0,
+                    DEFAULT_OFFSET,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_boolean(
+                    "chroma-subsample",
+                    "Chroma Subsample",
+                    "Average the chroma of every 2x2 pixels as 4:2:0 does (8 bit formats)",
+                    DEFAULT_CHROMA_SUBSAMPLE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "seed" => {
+                let seed = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing seed from {} to {}",
+                    settings.seed,
+                    seed
+                );
+                settings.seed = seed;
+            }
+            "bit-error-rate" => {
+                let bit_error_rate = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing bit-error-rate from {} to {}",
+                    settings.bit_error_rate,
+                    bit_error_rate
+                );
+                settings.bit_error_rate = bit_error_rate;
+            }
+            "burst-probability" => {
+                let burst_probability = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burst-probability from {} to {}",
+                    settings.burst_probability,
+                    burst_probability
+                );
+                settings.burst_probability = burst_probability;
+            }
+            "burst-length" => {
+                let burst_length = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing burst-length from {} to {}",
+                    settings.burst_length,
+                    burst_length
+                );
+                settings.burst_length = burst_length;
+            }
+            "line-blank-probability" => {
+                let line_blank_probability = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing line-blank-probability from {} to {}",
+                    settings.line_blank_probability,
+                    line_blank_probability
+                );
+                settings.line_blank_probability = line_blank_probability;
+            }
+            "drop-probability" => {
+                let drop_probability = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing drop-probability from {} to {}",
+                    settings.drop_probability,
+                    drop_probability
+                );
+                settings.drop_probability = drop_probability;
+            }
+            "repeat-probability" => {
+                let repeat_probability = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing repeat-probability from {} to {}",
+                    settings.repeat_probability,
+                    repeat_probability
+                );
+                settings.repeat_probability = repeat_probability;
+            }
+            "gain" => {
+                let gain = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing gain from {} to {}",
+                    settings.gain,
+                    gain
+                );
+                settings.gain = gain;
+            }
+            "offset" => {
+                let offset = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing offset from {} to {}",
+                    settings.offset,
+                    offset
+                );
+                settings.offset = offset;
+            }
+            "chroma-subsample" => {
+                let chroma_subsample = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing chroma-subsample from {} to {}",
+                    settings.chroma_subsample,
+                    chroma_subsample
+                );
+                settings.chroma_subsample = chroma_subsample;
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "seed" => settings.seed.to_value(),
+            "bit-error-rate" => settings.bit_error_rate.to_value(),
+            "burst-probability" => settings.burst_probability.to_value(),
+            "burst-length" => settings.burst_length.to_value(),
+            "line-blank-probability" => settings.line_blank_probability.to_value(),
+            "drop-probability" => settings.drop_probability.to_value(),
+            "repeat-probability" => settings.repeat_probability.to_value(),
+            "gain" => settings.gain.to_value(),
+            "offset" => settings.offset.to_value(),
+            "chroma-subsample" => settings.chroma_subsample.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for DataImpair {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Data Impair",
+                "Filter/Effect/Video",
+                "Impair video the way links and converters do, to test datasink",
+                "Reinis Muižnieks <muiznieks.reinis@gmail.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_simple(
+                "video/x-raw",
+                &[
+                    (
+                        "format",
+                        &gst::List::new(&[
+                            &gst_video::VideoFormat::Uyvy.to_str(),
+                            &gst_video::VideoFormat::Yuy2.to_str(),
+                            &gst_video::VideoFormat::I420.to_str(),
+                            &gst_video::VideoFormat::Nv12.to_str(),
+                            &gst_video::VideoFormat::Argb.to_str(),
+                            &gst_video::VideoFormat::Bgra.to_str(),
+                            &gst_video::VideoFormat::Rgba.to_str(),
+                            &gst_video::VideoFormat::Bgrx.to_str(),
+                            &gst_video::VideoFormat::Gray8.to_str(),
+                            &gst_video::VideoFormat::V210.to_str(),
+                        ]),
+                    ),
+                    ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                    ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                    (
+                        "framerate",
+                        &gst::FractionRange::new(
+                            gst::Fraction::new(0, 1),
+                            gst::Fraction::new(i32::MAX, 1),
+                        ),
+                    ),
+                ],
+            );
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseTransformImpl for DataImpair {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+    // Every run with the same seed starts from the same random numbers
+    fn start(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let seed = self.settings.lock().unwrap().seed;
+        *self.state.lock().unwrap() = State {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            ..Default::default()
+        };
+
+        gst_info!(CAT, obj: element, "Started with seed {}", seed);
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        // Reset state
+        *self.state.lock().unwrap() = Default::default();
+
+        gst_info!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn set_caps(
+        &self,
+        element: &Self::Type,
+        incaps: &gst::Caps,
+        _outcaps: &gst::Caps,
+    ) -> Result<(), gst::LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(incaps).map_err(|_| {
+            gst::loggable_error!(CAT, "Failed to build `VideoInfo` from caps {}", incaps)
+        })?;
+
+        gst_debug!(CAT, obj: element, "Configuring for caps {}", incaps);
+
+        let mut state = self.state.lock().unwrap();
+        state.black = black_frame(&info);
+        state.previous = None;
+        state.info = Some(info);
+
+        Ok(())
+    }
+
+    // Frames are dropped and repeated first, then impaired as a converter would
+    // and then as a link would
+    fn transform_ip(
+        &self,
+        element: &Self::Type,
+        buf: &mut gst::BufferRef,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let info = match state.info {
+            None => {
+                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                return Err(gst::FlowError::NotNegotiated);
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let pts = buf.pts();
+        if settings.drop_probability > 0.0 && state.rng.gen_bool(settings.drop_probability) {
+            gst_debug!(CAT, obj: element, "Dropping frame at {:?}", pts);
+            return Ok(gst_base::BASE_TRANSFORM_FLOW_DROPPED);
+        }
+
+        let mut map = buf.map_writable().map_err(|_| {
+            gst::element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+            gst::FlowError::Error
+        })?;
+        let frame = map.as_mut_slice();
+
+        if settings.repeat_probability > 0.0 && state.rng.gen_bool(settings.repeat_probability) {
+            match state.previous {
+                Some(ref previous) if previous.len() == frame.len() => {
+                    gst_debug!(CAT, obj: element, "Repeating the frame before {:?}", pts);
+                    frame.copy_from_slice(previous);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+                _ => (),
+            }
+        }
+
+        let eight_bit = info.format_info().bits() == 8;
+        if eight_bit && (settings.gain != DEFAULT_GAIN || settings.offset != DEFAULT_OFFSET) {
+            apply_gain(&info, frame, settings.gain, settings.offset);
+        }
+        if eight_bit && settings.chroma_subsample {
+            subsample_chroma(&info, frame);
+        }
+
+        if settings.line_blank_probability > 0.0 {
+            for y in 0..info.height() as usize {
+                if state.rng.gen_bool(settings.line_blank_probability) {
+                    gst_log!(CAT, obj: element, "Blanking line {}", y);
+                    blank_line(&info, &state.black, frame, y);
+                }
+            }
+        }
+
+        if !frame.is_empty()
+            && settings.burst_probability > 0.0
+            && state.rng.gen_bool(settings.burst_probability)
+        {
+            let start = state.rng.gen_range(0..frame.len());
+            let end = frame.len().min(start + settings.burst_length as usize);
+            gst_log!(CAT, obj: element, "Burst of errors at bytes {}..{}", start, end);
+            state.rng.fill(&mut frame[start..end]);
+        }
+
+        let flipped = flip_bits(&mut state.rng, frame, settings.bit_error_rate);
+        if flipped > 0 {
+            gst_log!(CAT, obj: element, "Flipped {} bits", flipped);
+        }
+
+        if settings.repeat_probability > 0.0 {
+            state.previous = Some(frame.to_vec());
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::sync::Once;
+
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            gst::init().unwrap();
+            crate::plugin_register_static().unwrap();
+        });
+    }
+
+    fn video_info(format: gst_video::VideoFormat, width: u32, height: u32) -> gst_video::VideoInfo {
+        gst_video::VideoInfo::builder(format, width, height)
+            .build()
+            .unwrap()
+    }
+
+    fn random_frame(info: &gst_video::VideoInfo, seed: u64) -> Vec<u8> {
+        let mut frame = vec![0; info.size()];
+        ChaCha8Rng::seed_from_u64(seed).fill(&mut frame[..]);
+        frame
+    }
+
+    #[test]
+    fn flip_bits_works() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let mut original = vec![0; 10000];
+        rng.fill(&mut original[..]);
+
+        let mut frame = original.clone();
+        assert_eq!(flip_bits(&mut rng, &mut frame, 0.0), 0);
+        assert_eq!(frame, original);
+
+        let flipped = flip_bits(&mut rng, &mut frame, 0.01);
+        let differing: usize = frame
+            .iter()
+            .zip(&original)
+            .map(|(a, b)| (a ^ b).count_ones() as usize)
+            .sum();
+        assert_eq!(differing, flipped);
+        // About 1% of 80000 bits
+        assert!((600..1000).contains(&flipped), "{}", flipped);
+
+        // The same seed flips the same bits
+        let flip = |seed| {
+            let mut frame = original.clone();
+            flip_bits(&mut ChaCha8Rng::seed_from_u64(seed), &mut frame, 0.01);
+            frame
+        };
+        assert_eq!(flip(7), flip(7));
+        assert_ne!(flip(7), flip(8));
+
+        let mut frame = original.clone();
+        assert_eq!(flip_bits(&mut rng, &mut frame, 1.0), 80000);
+        assert!(frame.iter().zip(&original).all(|(a, b)| *a == !b));
+    }
+
+    #[test]
+    fn blank_line_works() {
+        init();
+
+        let info = video_info(gst_video::VideoFormat::I420, 16, 8);
+        let black = black_frame(&info);
+        let original = random_frame(&info, 7);
+        let mut frame = original.clone();
+        blank_line(&info, &black, &mut frame, 3);
+
+        // Chroma lines are shared by two lines
+        for &(component, value, blanked) in &[(0, 16, 3), (1, 128, 1), (2, 128, 1)] {
+            let (width, height) = component_size(&info, component);
+            for y in 0..height {
+                for x in 0..width {
+                    let i = sample_offset(&info, component, x, y);
+                    let expected = if y == blanked { value } else { original[i] };
+                    assert_eq!(frame[i], expected, "{} at {}, {}", component, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apply_gain_works() {
+        init();
+
+        let info = video_info(gst_video::VideoFormat::I420, 16, 8);
+        let original = random_frame(&info, 7);
+        let mut frame = original.clone();
+        apply_gain(&info, &mut frame, 0.5, 10.0);
+        for_each_sample(&info, 0, |i| {
+            assert_eq!(frame[i], (original[i] as f64 * 0.5 + 10.0).round() as u8);
+        });
+        for component in 1..3 {
+            for_each_sample(&info, component, |i| {
+                let expected = 128.0 + (original[i] as f64 - 128.0) * 0.5;
+                assert_eq!(frame[i], expected.round() as u8);
+            });
+        }
+
+        // Clamped, and alpha stays as it is
+        let info = video_info(gst_video::VideoFormat::Argb, 8, 4);
+        let original = random_frame(&info, 7);
+        let mut frame = original.clone();
+        apply_gain(&info, &mut frame, 2.0, 0.0);
+        for component in 0..3 {
+            for_each_sample(&info, component, |i| {
+                assert_eq!(frame[i], (original[i] as u16 * 2).min(255) as u8);
+            });
+        }
+        for_each_sample(&info, 3, |i| assert_eq!(frame[i], original[i]));
+    }
+
+    #[test]
+    fn subsample_chroma_works() {
+        init();
+
+        // 4:2:2 loses the vertical detail of chroma
+        let info = video_info(gst_video::VideoFormat::Uyvy, 8, 4);
+        let original = random_frame(&info, 7);
+        let mut frame = original.clone();
+        subsample_chroma(&info, &mut frame);
+        for_each_sample(&info, 0, |i| assert_eq!(frame[i], original[i]));
+        for component in 1..3 {
+            let (width, height) = component_size(&info, component);
+            for y in (0..height).step_by(2) {
+                for x in 0..width {
+                    let (a, b) = (
+                        sample_offset(&info, component, x, y),
+                        sample_offset(&info, component, x, y + 1),
+                    );
+                    let average = ((original[a] as u16 + original[b] as u16 + 1) / 2) as u8;
+                    assert_eq!((frame[a], frame[b]), (average, average));
+                }
+            }
+        }
+
+        // 4:2:0 has nothing left to lose
+        let info = video_info(gst_video::VideoFormat::I420, 8, 4);
+        let original = random_frame(&info, 7);
+        let mut frame = original.clone();
+        subsample_chroma(&info, &mut frame);
+        assert_eq!(frame, original);
+
+        // Grey RGB has no chroma, a tint spreads to the pixels around it
+        let info = video_info(gst_video::VideoFormat::Bgrx, 2, 2);
+        let mut frame = vec![128; info.size()];
+        subsample_chroma(&info, &mut frame);
+        assert_eq!(frame, vec![128; info.size()]);
+        frame[sample_offset(&info, 0, 0, 0)] = 168;
+        subsample_chroma(&info, &mut frame);
+        assert!(frame[sample_offset(&info, 0, 0, 0)] < 168);
+        for &(x, y) in &[(1, 0), (0, 1), (1, 1)] {
+            assert!(frame[sample_offset(&info, 0, x, y)] > 128);
+            assert!(frame[sample_offset(&info, 1, x, y)] < 128);
+        }
+    }
+
+    #[test]
+    fn data_survives_impairment() {
+        init();
+
+        let pipeline = gst::parse_launch(
+            "datasrc name=src carriage=levels num-buffers=10 \
+             ! video/x-raw,format=UYVY,width=320,height=240,framerate=25/1 \
+             ! dataimpair seed=7 gain=0.8 offset=10 chroma-subsample=true \
+             ! datasink name=sink",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+        let sink = pipeline.by_name("sink").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        sink.connect("data-received", false, move |args| {
+            let data = args[1].get::<String>().unwrap();
+            sender.lock().unwrap().send(data).unwrap();
+            None
+        })
+        .unwrap();
+
+        pipeline.set_state(gst::State::Paused).unwrap();
+        src.emit_by_name("send-data", &[&"Hello"]).unwrap();
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        let message = pipeline.bus().unwrap().timed_pop_filtered(
+            gst::ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        pipeline.set_state(gst::State::Null).unwrap();
+        match message.as_ref().map(|message| message.view()) {
+            Some(gst::MessageView::Eos(..)) => (),
+            Some(gst::MessageView::Error(err)) => panic!("{}", err.error()),
+            _ => panic!("Timed out"),
+        }
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), ["Hello"]);
+    }
+}
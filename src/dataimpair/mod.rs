@@ -0,0 +1,23 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+// The public Rust wrapper type for our element
+glib::wrapper! {
+    pub struct DataImpair(ObjectSubclass<imp::DataImpair>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+// GStreamer elements need to be thread-safe. For the private implementation this is automatically
+// enforced but for the public wrapper type we need to specify this manually.
+unsafe impl Send for DataImpair {}
+unsafe impl Sync for DataImpair {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "dataimpair",
+        gst::Rank::None,
+        DataImpair::static_type(),
+    )
+}
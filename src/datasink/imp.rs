@@ -1,18 +1,46 @@
-use bytes::Buf;
 use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
-use gst::{gst_debug, gst_info};
+use gst::{gst_debug, gst_error, gst_info, gst_warning};
 use gst_base::subclass::prelude::*;
 use gst_video::subclass::prelude::VideoSinkImpl;
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs;
 use std::i32;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use once_cell::sync::Lazy;
 
-use crate::encoding::convert_back_with_0_and_255;
-use crate::encoding::convert_from_sdi_safe_payload;
+use crate::captions::caption_caps;
+use crate::captions::caption_src_caps;
+use crate::encoding::captions::unpack_captions;
+use crate::encoding::decompress;
+use crate::encoding::klv;
+use crate::encoding::scte35;
+use crate::encoding::st2038;
+use crate::encoding::unix_time_us;
+use crate::encoding::DecodeError;
+use crate::encoding::DecodedFrame;
+use crate::encoding::FrameDecoder;
+use crate::encoding::FrameLayout;
+use crate::encoding::Keys;
+use crate::encoding::Record;
+use crate::encoding::RecordType;
+use crate::encoding::ReplayWindow;
+use crate::encoding::VerifyError;
 use crate::encoding::MAGIC_NUMBER;
+use crate::encoding::PACKED_MAGIC_NUMBER;
+use crate::encoding::RECORD_FLAG_COMPRESSED;
+use crate::encoding::RECORD_FLAG_ENCRYPTED;
+use crate::encoding::RECORD_FLAG_SIGNED;
+use crate::encoding::STUFFED_MAGIC_NUMBER;
+use crate::keys::load_keys;
+use crate::mpegts::new_scte35_event;
+use crate::timecode::to_video_time_code;
+use crate::variant::variant_from_bytes;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -23,11 +51,691 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 });
 
 const SIGNAL_DATA_RECEIVED: &str = "data-received";
+const SIGNAL_DATA_RECEIVED_ON_CHANNEL: &str = "data-received-on-channel";
+const SIGNAL_DATA_REJECTED: &str = "data-rejected";
+const SIGNAL_STRUCTURE_RECEIVED: &str = "structure-received";
+const SIGNAL_VARIANT_RECEIVED: &str = "variant-received";
+const SIGNAL_NEW_DATA: &str = "new-data";
+const SIGNAL_SYNC_ACQUIRED: &str = "sync-acquired";
 
+const DEFAULT_REPLAY_WINDOW: u64 = 5000;
+const DEFAULT_MAX_DECOMPRESSED_SIZE: u32 = 16 * 1024 * 1024;
+const DEFAULT_KLV_CHECKSUM: bool = false;
+// First line of VANC after the switching line, ST 2038 has no line 0
+const DEFAULT_ANC_LINE: u32 = 9;
+// Type 2 packets for user applications
+const DEFAULT_ANC_DID: u32 = 0x50;
+const DEFAULT_ANC_SDID: u32 = 0x01;
+const DEFAULT_EMIT_SIGNALS: bool = true;
+const DEFAULT_MAX_BUFFERED: u32 = 0;
+const DEFAULT_DROP_POLICY: DropPolicy = DropPolicy::DropOldest;
+const DEFAULT_SYNC_SEARCH: bool = false;
+const DEFAULT_SEARCH_START_LINE: u32 = 0;
+const DEFAULT_SEARCH_LINES: u32 = 16;
+const DEFAULT_SEARCH_START_COLUMN: u32 = 0;
+const DEFAULT_SEARCH_COLUMNS: u32 = 64;
+const DEFAULT_LINE_MARGIN: u32 = 0;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSinkDropPolicy")]
+pub enum DropPolicy {
+    #[genum(name = "Drop the oldest message", nick = "drop-oldest")]
+    DropOldest = 0,
+    #[genum(name = "Drop the newest message", nick = "drop-newest")]
+    DropNewest = 1,
+    #[genum(
+        name = "Block the streaming thread until there is room",
+        nick = "block"
+    )]
+    Block = 2,
+}
+
+#[derive(Clone)]
+struct Settings {
+    // Channels to signal, all of them if empty
+    channels: Vec<u8>,
+    key: String,
+    key_file: Option<String>,
+    // In milliseconds
+    replay_window: u64,
+    compression_dictionary: Option<String>,
+    max_decompressed_size: u32,
+    klv_checksum: bool,
+    anc_line: u32,
+    anc_did: u8,
+    anc_sdid: u8,
+    emit_signals: bool,
+    // Nothing is buffered for `pull-data` if 0
+    max_buffered: u32,
+    drop_policy: DropPolicy,
+    sync_search: bool,
+    // Frames are read line by line if not 0, see `lines`
+    line_margin: u32,
+    search_start_line: u32,
+    search_lines: u32,
+    search_start_column: u32,
+    search_columns: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            channels: Vec::new(),
+            key: String::new(),
+            key_file: None,
+            replay_window: DEFAULT_REPLAY_WINDOW,
+            compression_dictionary: None,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            klv_checksum: DEFAULT_KLV_CHECKSUM,
+            anc_line: DEFAULT_ANC_LINE,
+            anc_did: DEFAULT_ANC_DID as u8,
+            anc_sdid: DEFAULT_ANC_SDID as u8,
+            emit_signals: DEFAULT_EMIT_SIGNALS,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            drop_policy: DEFAULT_DROP_POLICY,
+            sync_search: DEFAULT_SYNC_SEARCH,
+            line_margin: DEFAULT_LINE_MARGIN,
+            search_start_line: DEFAULT_SEARCH_START_LINE,
+            search_lines: DEFAULT_SEARCH_LINES,
+            search_start_column: DEFAULT_SEARCH_START_COLUMN,
+            search_columns: DEFAULT_SEARCH_COLUMNS,
+        }
+    }
+}
+
+fn parse_channels(channels: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
+    channels
+        .split(',')
+        .map(str::trim)
+        .filter(|channel| !channel.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Checks that `data` is whole KLV packets, and the checksums of MISB ST 0601 sets
+/// among them if `checksum` is set
+fn check_klv(data: &[u8], checksum: bool) -> Result<(), klv::KlvError> {
+    for packet in klv::parse_klv_packets(data)? {
+        if checksum {
+            packet.check_misb_0601()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What a src pad outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PadContent {
+    Records(RecordType),
+    /// Whole frames as ST 2038 packets
+    St2038,
+}
+
+struct ChannelPad {
+    pad: gst::Pad,
+    // Every channel if `None`
+    channel: Option<u8>,
+    content: PadContent,
+    needs_initial_events: bool,
+    // Last caps pushed
+    caps: Option<gst::Caps>,
+}
+
+impl ChannelPad {
+    fn carries(&self, content: PadContent, channel: u8) -> bool {
+        self.content == content && self.channel.map_or(true, |c| c == channel)
+    }
+}
+
+#[derive(Default)]
+struct SrcPads {
+    pads: Vec<ChannelPad>,
+    // Last segment received from upstream, forwarded to every src pad
+    segment: Option<gst::Event>,
+}
+
+#[derive(Default)]
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    // Loaded from key and key-file on start, only encrypted or signed records are
+    // accepted if there are any
+    keys: Keys,
+    replay_window: ReplayWindow,
+    dictionary: Arc<Vec<u8>>,
+    rejected: u64,
+    replayed: u64,
+    // Signed records accepted from the last frame that had any, which a repeat
+    // of that frame carries again
+    last_signed: Vec<Vec<u8>>,
+    // Where `sync-search` last found a frame
+    sync: Option<SyncPosition>,
+}
+
+/// Where a frame starts in the picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SyncPosition {
+    line: u32,
+    column: u32,
+}
+
+/// Frame starting `offset` bytes into `frame`
+fn decode_at(frame: &[u8], offset: usize) -> Result<Option<DecodedFrame>, DecodeError> {
+    FrameDecoder::new(FrameLayout::new(frame.len() - offset)).decode(&frame[offset..])
+}
+
+/// Whether `data` starts with the magic number of any kind of frame
+fn starts_frame(data: &[u8]) -> bool {
+    match *data {
+        [a, b, c, d, ..] => matches!(
+            u32::from_be_bytes([a, b, c, d]),
+            MAGIC_NUMBER | PACKED_MAGIC_NUMBER | STUFFED_MAGIC_NUMBER
+        ),
+        _ => false,
+    }
+}
+
+/// Frame data of `frame` from `position` on. With a `line-margin` every line is
+/// read from the column of `position` on, see `lines`, otherwise unshifted
+/// pictures are read as is. `None` if the column doesn't start on a byte. v210
+/// pictures have to be read with `lines::read_v210` first.
+fn frame_data_at<'a>(
+    settings: &Settings,
+    info: &gst_video::VideoInfo,
+    frame: &'a [u8],
+    position: SyncPosition,
+) -> Option<Cow<'a, [u8]>> {
+    if settings.line_margin == 0 && position.column == 0 {
+        let start = (position.line as usize * crate::lines::stride(info)).min(frame.len());
+        return Some(Cow::Borrowed(&frame[start..]));
+    }
+
+    crate::lines::shifted_layout(info, settings.line_margin, position.line, position.column)
+        .map(|layout| layout.read(frame))
+}
+
+/// First frame found at the start of `search-columns` pixels from
+/// `search-start-column` on, in `search-lines` lines from `search-start-line` on,
+/// row by row
+fn search_frame<'a>(
+    settings: &Settings,
+    info: &gst_video::VideoInfo,
+    frame: &'a [u8],
+) -> Option<(SyncPosition, DecodedFrame, Cow<'a, [u8]>)> {
+    let stride = crate::lines::stride(info);
+    let lines = settings.search_start_line
+        ..settings
+            .search_start_line
+            .saturating_add(settings.search_lines)
+            .min(info.height());
+    let columns = settings.search_start_column
+        ..settings
+            .search_start_column
+            .saturating_add(settings.search_columns)
+            .min(info.width());
+
+    for line in lines {
+        for column in columns.clone() {
+            // Frames only start on pixels with a byte of their own
+            let offset = match crate::lines::column_offset(info, column) {
+                Some(offset) => line as usize * stride + offset,
+                None => continue,
+            };
+            if offset >= frame.len() {
+                return None;
+            }
+            // Only join the lines up where a frame may start
+            if !starts_frame(&frame[offset..]) {
+                continue;
+            }
+
+            let position = SyncPosition { line, column };
+            let data = match frame_data_at(settings, info, frame, position) {
+                Some(data) => data,
+                None => continue,
+            };
+            // The CRC keeps frames that lost data to the shift from being found
+            if let Ok(Some(decoded)) = decode_at(&data, 0) {
+                return Some((position, decoded, data));
+            }
+        }
+    }
+
+    None
+}
+
+/// Received messages waiting for `pull-data`
 #[derive(Default)]
-pub struct DataSink {}
+struct ReceivedQueue {
+    messages: VecDeque<gst::Structure>,
+    // Set while stopped or flushing, so blocked streaming threads and `pull-data`
+    // callers are released
+    flushing: bool,
+    eos: bool,
+}
+
+/// Message as `pull-data` returns it, named after its kind with `value` in the field
+/// of the same name
+fn received_message(
+    kind: &str,
+    value: glib::SendValue,
+    channel: u8,
+    timecode: Option<&str>,
+) -> gst::Structure {
+    let mut message = gst::Structure::new_empty(kind);
+    message.set_value(kind, value);
+    message.set("channel", &(channel as u32));
+    if let Some(timecode) = timecode {
+        message.set("timecode", &timecode);
+    }
+    message
+}
+
+#[derive(Default)]
+pub struct DataSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    src_pads: Mutex<SrcPads>,
+    received: Mutex<ReceivedQueue>,
+    received_changed: Condvar,
+}
+
+impl DataSink {
+    fn initial_events(
+        &self,
+        element: &super::DataSink,
+        pad: &gst::Pad,
+        caps: &gst::Caps,
+        src_pads: &SrcPads,
+    ) -> Vec<gst::Event> {
+        let stream_id = pad.create_stream_id(element, Some(pad.name().as_str()));
+        let segment = src_pads.segment.clone().unwrap_or_else(|| {
+            gst::event::Segment::new(&gst::FormattedSegment::<gst::ClockTime>::new())
+        });
+
+        vec![
+            gst::event::StreamStart::new(&stream_id),
+            gst::event::Caps::new(caps),
+            segment,
+        ]
+    }
+
+    /// Pads carrying content of a channel (all of them for `None`) together with the
+    /// events to push before any data. `caps` are only needed for pads that don't
+    /// have fixed template caps.
+    fn channel_pads(
+        &self,
+        element: &super::DataSink,
+        content: Option<(PadContent, u8)>,
+        caps: Option<&gst::Caps>,
+    ) -> Vec<(gst::Pad, Vec<gst::Event>)> {
+        let mut src_pads = self.src_pads.lock().unwrap();
+
+        let mut pads = Vec::new();
+        for i in 0..src_pads.pads.len() {
+            if let Some((content, channel)) = content {
+                if !src_pads.pads[i].carries(content, channel) {
+                    continue;
+                }
+            }
+
+            let pad = src_pads.pads[i].pad.clone();
+            let events = if src_pads.pads[i].needs_initial_events {
+                let caps = caps.cloned().unwrap_or_else(|| {
+                    let mut caps = pad.pad_template_caps();
+                    caps.fixate();
+                    caps
+                });
+                let events = self.initial_events(element, &pad, &caps, &src_pads);
+                src_pads.pads[i].needs_initial_events = false;
+                src_pads.pads[i].caps = Some(caps);
+                events
+            } else {
+                match caps {
+                    Some(caps) if src_pads.pads[i].caps.as_ref() != Some(caps) => {
+                        src_pads.pads[i].caps = Some(caps.clone());
+                        vec![gst::event::Caps::new(caps)]
+                    }
+                    _ => Vec::new(),
+                }
+            };
+            pads.push((pad, events));
+        }
+
+        pads
+    }
+
+    /// Pushes `record` of the video `frame` on the pads of its type and channel
+    fn push_to_channel_pads(
+        &self,
+        element: &super::DataSink,
+        record_type: RecordType,
+        record: &Record,
+        caps: Option<&gst::Caps>,
+        frame: &gst::Buffer,
+        timecode: Option<&gst_video::ValidVideoTimeCode>,
+    ) {
+        let content = Some((PadContent::Records(record_type), record.channel));
+        for (pad, events) in self.channel_pads(element, content, caps) {
+            self.push_to_pad(element, &pad, events, record.data, frame, timecode);
+        }
+    }
+
+    /// Pushes the coded `data` of the video `frame` as ST 2038 packets on the
+    /// `st2038` pad, if there is one
+    fn push_st2038(
+        &self,
+        element: &super::DataSink,
+        data: &[u8],
+        info: &gst_video::VideoInfo,
+        frame: &gst::Buffer,
+        timecode: Option<&gst_video::ValidVideoTimeCode>,
+    ) {
+        let pads = self.channel_pads(element, Some((PadContent::St2038, 0)), None);
+        if pads.is_empty() {
+            return;
+        }
+
+        let (line, did, sdid) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.anc_line, settings.anc_did, settings.anc_sdid)
+        };
+        let packets = match st2038::packetize(
+            did,
+            sdid,
+            data,
+            line.min(u16::MAX as u32) as u16,
+            info.width() as usize,
+        ) {
+            Ok(packets) => packets,
+            Err(err) => {
+                gst_warning!(CAT, obj: element, "Not outputting frame: {}", err);
+                return;
+            }
+        };
+        let mut output = Vec::new();
+        for packet in &packets {
+            packet.write(&mut output);
+        }
+
+        for (pad, events) in pads {
+            self.push_to_pad(element, &pad, events, &output, frame, timecode);
+        }
+    }
+
+    /// Pushes `events` and then `data` with the timestamps of the video `frame`
+    fn push_to_pad(
+        &self,
+        element: &super::DataSink,
+        pad: &gst::Pad,
+        events: Vec<gst::Event>,
+        data: &[u8],
+        frame: &gst::Buffer,
+        timecode: Option<&gst_video::ValidVideoTimeCode>,
+    ) {
+        for event in events {
+            pad.push_event(event);
+        }
+
+        let mut buffer = gst::Buffer::from_mut_slice(data.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(frame.pts());
+            buffer.set_duration(frame.duration());
+            if let Some(timecode) = timecode {
+                gst_video::VideoTimeCodeMeta::add(buffer, timecode);
+            }
+        }
+
+        if let Err(err) = pad.push(buffer) {
+            gst_debug!(CAT, obj: element, "Failed to push on {}: {:?}", pad.name(), err);
+        }
+    }
+
+    /// Data of `record` after checking, decrypting and decompressing it as its
+    /// flags say, signed records that are accepted are added to `signed`. `None`
+    /// for signed records the same as one of the last frame that had any, which a
+    /// repeated video frame carries again, they are only counted in `replayed`.
+    /// Other signed records received before are rejected.
+    fn decode_record<'a>(
+        &self,
+        frame_header: &[u8],
+        record: &Record<'a>,
+        keys: &Keys,
+        dictionary: &[u8],
+        settings: &Settings,
+        signed: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<Cow<'a, [u8]>>, (gst::StreamError, String)> {
+        let aad = Record::aad(frame_header, record.channel, record.flags);
+        let data = if record.flags & RECORD_FLAG_ENCRYPTED != 0 {
+            keys.decrypt(&aad, record.data)
+                .map(Cow::Owned)
+                .map_err(|err| (gst::StreamError::Decrypt, err.to_string()))?
+        } else if record.flags & RECORD_FLAG_SIGNED != 0 {
+            let verified = keys.verify(&aad, record.data).and_then(|verified| {
+                self.state.lock().unwrap().replay_window.check(
+                    &verified,
+                    unix_time_us(),
+                    settings.replay_window.saturating_mul(1000),
+                )?;
+                Ok(verified)
+            });
+            match verified {
+                Ok(verified) => {
+                    signed.push(record.data.to_vec());
+                    Cow::Borrowed(verified.payload)
+                }
+                Err(err @ VerifyError::Replayed(_)) => {
+                    let mut state = self.state.lock().unwrap();
+                    if !state.last_signed.iter().any(|data| data == record.data) {
+                        return Err((gst::StreamError::Decrypt, err.to_string()));
+                    }
+                    state.replayed += 1;
+                    return Ok(None);
+                }
+                Err(err) => return Err((gst::StreamError::Decrypt, err.to_string())),
+            }
+        } else if !keys.is_empty() {
+            return Err((
+                gst::StreamError::Decrypt,
+                "Record is not encrypted or signed".to_string(),
+            ));
+        } else {
+            Cow::Borrowed(record.data)
+        };
+
+        if record.flags & RECORD_FLAG_COMPRESSED == 0 {
+            return Ok(Some(data));
+        }
+
+        decompress(dictionary, &data, settings.max_decompressed_size as usize)
+            .map(|data| Some(Cow::Owned(data)))
+            .map_err(|err| (gst::StreamError::Decode, err.to_string()))
+    }
+
+    /// Counts and reports a record dropped because it can't be trusted or decoded
+    fn reject(
+        &self,
+        element: &super::DataSink,
+        channel: u8,
+        error: gst::StreamError,
+        reason: &str,
+    ) {
+        self.state.lock().unwrap().rejected += 1;
+
+        gst::element_warning!(
+            element,
+            error,
+            ["Dropping record on channel {}: {}", channel, reason]
+        );
+
+        element
+            .emit_by_name(SIGNAL_DATA_REJECTED, &[&(channel as u32), &reason])
+            .unwrap();
+    }
+
+    /// Keeps `message` for `pull-data` if `max-buffered` is set, dropping messages or
+    /// waiting for room as `drop-policy` says
+    fn buffer_received(&self, element: &super::DataSink, message: gst::Structure) {
+        let mut received = self.received.lock().unwrap();
+        loop {
+            // Read again after every wait, the app may have changed them meanwhile
+            let (max_buffered, drop_policy) = {
+                let settings = self.settings.lock().unwrap();
+                (settings.max_buffered, settings.drop_policy)
+            };
+            if max_buffered == 0 {
+                return;
+            }
+            if received.messages.len() < max_buffered as usize {
+                break;
+            }
+
+            match drop_policy {
+                DropPolicy::DropOldest => {
+                    gst_debug!(CAT, obj: element, "Buffer is full, dropping oldest message");
+                    received.messages.pop_front();
+                }
+                DropPolicy::DropNewest => {
+                    gst_debug!(CAT, obj: element, "Buffer is full, dropping newest message");
+                    return;
+                }
+                DropPolicy::Block => {
+                    if received.flushing {
+                        gst_debug!(CAT, obj: element, "Flushing, dropping message");
+                        return;
+                    }
+                    gst_debug!(CAT, obj: element, "Buffer is full, waiting for room");
+                    received = self.received_changed.wait(received).unwrap();
+                }
+            }
+        }
+
+        received.messages.push_back(message);
+        drop(received);
+        self.received_changed.notify_all();
+
+        if self.settings.lock().unwrap().emit_signals {
+            element.emit_by_name(SIGNAL_NEW_DATA, &[]).unwrap();
+        }
+    }
+
+    /// Oldest buffered message, waiting up to `timeout` (forever for `None`) for one.
+    /// `None` on timeout, after EOS or while flushing.
+    fn pull(&self, timeout: Option<Duration>) -> Option<gst::Structure> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut received = self.received.lock().unwrap();
+        loop {
+            if let Some(message) = received.messages.pop_front() {
+                drop(received);
+                self.received_changed.notify_all();
+                return Some(message);
+            }
+            if received.flushing || received.eos {
+                return None;
+            }
+
+            received = match deadline {
+                None => self.received_changed.wait(received).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.received_changed
+                        .wait_timeout(received, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Releases whoever waits on the buffered messages, and drops them if `clear`
+    fn set_flushing(&self, flushing: bool, clear: bool) {
+        let mut received = self.received.lock().unwrap();
+        received.flushing = flushing;
+        if clear {
+            received.messages.clear();
+            received.eos = false;
+        }
+        drop(received);
+        self.received_changed.notify_all();
+    }
+
+    /// Frame at the position it was last found at, or else wherever a search finds
+    /// one, with the frame data from where it starts. Moving to a new position is
+    /// signalled with `sync-acquired`.
+    fn sync<'a>(
+        &self,
+        element: &super::DataSink,
+        settings: &Settings,
+        info: &gst_video::VideoInfo,
+        frame: &'a [u8],
+    ) -> Result<Option<(DecodedFrame, Cow<'a, [u8]>)>, DecodeError> {
+        let locked = self.state.lock().unwrap().sync;
+        let mut error = None;
+        if let Some(data) =
+            locked.and_then(|position| frame_data_at(settings, info, frame, position))
+        {
+            // Frames without data don't lose the lock, and bad data where the frame
+            // was only does if a frame is found elsewhere
+            match decode_at(&data, 0) {
+                Ok(Some(decoded)) => return Ok(Some((decoded, data))),
+                Ok(None) => (),
+                Err(err) => error = Some(err),
+            }
+        }
+
+        let (position, decoded, data) = match search_frame(settings, info, frame) {
+            Some(found) => found,
+            None => return error.map_or(Ok(None), Err),
+        };
+
+        if locked != Some(position) {
+            gst_info!(
+                CAT,
+                obj: element,
+                "Found frame on line {} at column {}",
+                position.line,
+                position.column
+            );
+            self.state.lock().unwrap().sync = Some(position);
+            element
+                .emit_by_name(SIGNAL_SYNC_ACQUIRED, &[&position.line, &position.column])
+                .unwrap();
+        }
+
+        Ok(Some((decoded, data)))
+    }
 
-impl DataSink {}
+    fn forward_event(&self, element: &super::DataSink, event: &gst::Event) {
+        // Pads without data get the sticky events with their first buffer, only EOS and
+        // SCTE-35 events can't wait
+        let pads = match event.view() {
+            gst::EventView::Eos(..) | gst::EventView::CustomDownstream(..) => {
+                self.channel_pads(element, None, None)
+            }
+            _ => self
+                .src_pads
+                .lock()
+                .unwrap()
+                .pads
+                .iter()
+                .filter(|channel_pad| !channel_pad.needs_initial_events)
+                .map(|channel_pad| (channel_pad.pad.clone(), Vec::new()))
+                .collect(),
+        };
+
+        for (pad, events) in pads {
+            for event in events {
+                pad.push_event(event);
+            }
+            pad.push_event(event.clone());
+        }
+    }
+}
 
 #[glib::object_subclass]
 impl ObjectSubclass for DataSink {
@@ -37,15 +745,558 @@ impl ObjectSubclass for DataSink {
 }
 
 impl ObjectImpl for DataSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpec::new_string(
+                    "channels",
+                    "Channels",
+                    "Comma separated list of channels to signal data-received for (empty = all)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_string(
+                    "key",
+                    "Key",
+                    "Keys to decrypt or verify messages with as [<id>:]<64 hex digits>, \
+                     separated by commas (empty = accept messages as they are)",
+                    None,
+                    glib::ParamFlags::WRITABLE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_string(
+                    "key-file",
+                    "Key File",
+                    "File with more keys in the same format as key",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint64(
+                    "replay-window",
+                    "Replay Window",
+                    "How far in milliseconds the timestamp of a signed message may be from \
+                     the clock (0 = only check that timestamps increase)",
+                    0,
+                    u64::MAX,
+                    DEFAULT_REPLAY_WINDOW,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_string(
+                    "compression-dictionary",
+                    "Compression Dictionary",
+                    "File with the dictionary datasrc compresses messages with",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "max-decompressed-size",
+                    "Max Decompressed Size",
+                    "Largest size in bytes a message may decompress to, larger ones are rejected",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_DECOMPRESSED_SIZE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "anc-line",
+                    "ANC Line",
+                    "Line the ST 2038 packets of the st2038 pad start on",
+                    0,
+                    u32::MAX,
+                    DEFAULT_ANC_LINE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "anc-did",
+                    "ANC DID",
+                    "Data identifier of the ST 2038 packets of the st2038 pad",
+                    0,
+                    u8::MAX as u32,
+                    DEFAULT_ANC_DID,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "anc-sdid",
+                    "ANC SDID",
+                    "Secondary data identifier of the ST 2038 packets of the st2038 pad",
+                    0,
+                    u8::MAX as u32,
+                    DEFAULT_ANC_SDID,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_boolean(
+                    "klv-checksum",
+                    "KLV Checksum",
+                    "Reject KLV with MISB ST 0601 local sets that have no or a wrong checksum",
+                    DEFAULT_KLV_CHECKSUM,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_boolean(
+                    "emit-signals",
+                    "Emit Signals",
+                    "Emit data-received, data-received-on-channel, structure-received, variant-received and new-data",
+                    DEFAULT_EMIT_SIGNALS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "max-buffered",
+                    "Max Buffered",
+                    "Number of received messages kept for pull-data (0 = none)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_BUFFERED,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_enum(
+                    "drop-policy",
+                    "Drop Policy",
+                    "What to do when max-buffered messages are waiting for pull-data",
+                    DropPolicy::static_type(),
+                    DEFAULT_DROP_POLICY as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "buffered",
+                    "Buffered",
+                    "Number of received messages waiting for pull-data",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpec::new_boolean(
+                    "sync-search",
+                    "Sync Search",
+                    "Search for frames that don't start at the top left of the picture",
+                    DEFAULT_SYNC_SEARCH,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "line-margin",
+                    "Line Margin",
+                    "Pixels at the right end of every line datasrc left out of frames, the \
+                     same as its line-margin (0 = frames fill the whole picture)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_LINE_MARGIN,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "search-start-line",
+                    "Search Start Line",
+                    "First line to search for frames",
+                    0,
+                    u32::MAX,
+                    DEFAULT_SEARCH_START_LINE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "search-lines",
+                    "Search Lines",
+                    "Number of lines from search-start-line on to search for frames",
+                    1,
+                    u32::MAX,
+                    DEFAULT_SEARCH_LINES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "search-start-column",
+                    "Search Start Column",
+                    "First pixel of every searched line to search for frames",
+                    0,
+                    u32::MAX,
+                    DEFAULT_SEARCH_START_COLUMN,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint(
+                    "search-columns",
+                    "Search Columns",
+                    "Number of pixels from search-start-column on to search for frames",
+                    1,
+                    u32::MAX,
+                    DEFAULT_SEARCH_COLUMNS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint64(
+                    "rejected",
+                    "Rejected",
+                    "Number of messages dropped because they failed authentication, were outside \
+                     the replay window, were replayed, could not be decompressed or were invalid KLV",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpec::new_uint64(
+                    "replayed",
+                    "Replayed",
+                    "Number of signed messages skipped because they came again in a repeated \
+                     video frame",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "channels" => {
+                let channels = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_default();
+                match parse_channels(&channels) {
+                    Ok(channels) => {
+                        gst_info!(CAT, obj: obj, "Changing channels to {:?}", channels);
+                        settings.channels = channels;
+                    }
+                    Err(err) => {
+                        gst_error!(CAT, obj: obj, "Invalid channels {:?}: {}", channels, err);
+                    }
+                }
+            }
+            "key" => {
+                gst_info!(CAT, obj: obj, "Changing key");
+                settings.key = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_default();
+            }
+            "key-file" => {
+                let key_file = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing key-file from {:?} to {:?}",
+                    settings.key_file,
+                    key_file
+                );
+                settings.key_file = key_file;
+            }
+            "replay-window" => {
+                let replay_window = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing replay-window from {} to {}",
+                    settings.replay_window,
+                    replay_window
+                );
+                settings.replay_window = replay_window;
+            }
+            "compression-dictionary" => {
+                let compression_dictionary = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing compression-dictionary from {:?} to {:?}",
+                    settings.compression_dictionary,
+                    compression_dictionary
+                );
+                settings.compression_dictionary = compression_dictionary;
+            }
+            "max-decompressed-size" => {
+                let max_decompressed_size = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing max-decompressed-size from {} to {}",
+                    settings.max_decompressed_size,
+                    max_decompressed_size
+                );
+                settings.max_decompressed_size = max_decompressed_size;
+            }
+            "anc-line" => {
+                let anc_line = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing anc-line from {} to {}",
+                    settings.anc_line,
+                    anc_line
+                );
+                settings.anc_line = anc_line;
+            }
+            "anc-did" => {
+                let anc_did = value.get::<u32>().expect("type checked upstream") as u8;
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing anc-did from {:#04x} to {:#04x}",
+                    settings.anc_did,
+                    anc_did
+                );
+                settings.anc_did = anc_did;
+            }
+            "anc-sdid" => {
+                let anc_sdid = value.get::<u32>().expect("type checked upstream") as u8;
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing anc-sdid from {:#04x} to {:#04x}",
+                    settings.anc_sdid,
+                    anc_sdid
+                );
+                settings.anc_sdid = anc_sdid;
+            }
+            "klv-checksum" => {
+                let klv_checksum = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing klv-checksum from {} to {}",
+                    settings.klv_checksum,
+                    klv_checksum
+                );
+                settings.klv_checksum = klv_checksum;
+            }
+            "emit-signals" => {
+                let emit_signals = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing emit-signals from {} to {}",
+                    settings.emit_signals,
+                    emit_signals
+                );
+                settings.emit_signals = emit_signals;
+            }
+            "max-buffered" => {
+                let max_buffered = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing max-buffered from {} to {}",
+                    settings.max_buffered,
+                    max_buffered
+                );
+                settings.max_buffered = max_buffered;
+            }
+            "drop-policy" => {
+                let drop_policy = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing drop-policy from {:?} to {:?}",
+                    settings.drop_policy,
+                    drop_policy
+                );
+                settings.drop_policy = drop_policy;
+            }
+            "sync-search" => {
+                let sync_search = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing sync-search from {} to {}",
+                    settings.sync_search,
+                    sync_search
+                );
+                settings.sync_search = sync_search;
+            }
+            "line-margin" => {
+                let line_margin = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing line-margin from {} to {}",
+                    settings.line_margin,
+                    line_margin
+                );
+                settings.line_margin = line_margin;
+            }
+            "search-start-line" => {
+                let search_start_line = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing search-start-line from {} to {}",
+                    settings.search_start_line,
+                    search_start_line
+                );
+                settings.search_start_line = search_start_line;
+            }
+            "search-start-column" => {
+                let search_start_column = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing search-start-column from {} to {}",
+                    settings.search_start_column,
+                    search_start_column
+                );
+                settings.search_start_column = search_start_column;
+            }
+            "search-lines" => {
+                let search_lines = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing search-lines from {} to {}",
+                    settings.search_lines,
+                    search_lines
+                );
+                settings.search_lines = search_lines;
+            }
+            "search-columns" => {
+                let search_columns = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing search-columns from {} to {}",
+                    settings.search_columns,
+                    search_columns
+                );
+                settings.search_columns = search_columns;
+            }
+            _ => unimplemented!(),
+        }
+        drop(settings);
+
+        // A blocked streaming thread may have room now. Taking the lock first makes
+        // sure it is waiting already rather than about to miss this.
+        drop(self.received.lock().unwrap());
+        self.received_changed.notify_all();
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "channels" => self
+                .settings
+                .lock()
+                .unwrap()
+                .channels
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+                .to_value(),
+            "key-file" => self.settings.lock().unwrap().key_file.to_value(),
+            "replay-window" => self.settings.lock().unwrap().replay_window.to_value(),
+            "compression-dictionary" => self
+                .settings
+                .lock()
+                .unwrap()
+                .compression_dictionary
+                .to_value(),
+            "max-decompressed-size" => self
+                .settings
+                .lock()
+                .unwrap()
+                .max_decompressed_size
+                .to_value(),
+            "anc-line" => self.settings.lock().unwrap().anc_line.to_value(),
+            "anc-did" => (self.settings.lock().unwrap().anc_did as u32).to_value(),
+            "anc-sdid" => (self.settings.lock().unwrap().anc_sdid as u32).to_value(),
+            "klv-checksum" => self.settings.lock().unwrap().klv_checksum.to_value(),
+            "emit-signals" => self.settings.lock().unwrap().emit_signals.to_value(),
+            "max-buffered" => self.settings.lock().unwrap().max_buffered.to_value(),
+            "drop-policy" => self.settings.lock().unwrap().drop_policy.to_value(),
+            "sync-search" => self.settings.lock().unwrap().sync_search.to_value(),
+            "line-margin" => self.settings.lock().unwrap().line_margin.to_value(),
+            "search-start-line" => self.settings.lock().unwrap().search_start_line.to_value(),
+            "search-lines" => self.settings.lock().unwrap().search_lines.to_value(),
+            "search-start-column" => self.settings.lock().unwrap().search_start_column.to_value(),
+            "search-columns" => self.settings.lock().unwrap().search_columns.to_value(),
+            "buffered" => (self.received.lock().unwrap().messages.len() as u32).to_value(),
+            "rejected" => self.state.lock().unwrap().rejected.to_value(),
+            "replayed" => self.state.lock().unwrap().replayed.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
     fn signals() -> &'static [glib::subclass::Signal] {
         static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
-            vec![glib::subclass::Signal::builder(
-                SIGNAL_DATA_RECEIVED,
-                &[String::static_type().into()],
-                glib::types::Type::UNIT.into(),
-            )
-            // .action()
-            .build()]
+            vec![
+                glib::subclass::Signal::builder(
+                    SIGNAL_DATA_RECEIVED,
+                    &[String::static_type().into()],
+                    glib::types::Type::UNIT.into(),
+                )
+                // .action()
+                .build(),
+                // data-received with the channel and the timecode of the frame
+                glib::subclass::Signal::builder(
+                    SIGNAL_DATA_RECEIVED_ON_CHANNEL,
+                    &[
+                        String::static_type().into(),
+                        u32::static_type().into(),
+                        String::static_type().into(),
+                    ],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_STRUCTURE_RECEIVED,
+                    &[
+                        gst::Structure::static_type().into(),
+                        u32::static_type().into(),
+                        String::static_type().into(),
+                    ],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_VARIANT_RECEIVED,
+                    &[
+                        glib::Variant::static_type().into(),
+                        u32::static_type().into(),
+                        String::static_type().into(),
+                    ],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    "pull-data",
+                    &[u64::static_type().into()],
+                    gst::Structure::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSink>().expect("signal arg");
+                    let timeout = args[1].get::<u64>().expect("signal arg");
+
+                    // GST_CLOCK_TIME_NONE waits forever
+                    let timeout = if timeout == u64::MAX {
+                        None
+                    } else {
+                        Some(Duration::from_nanos(timeout))
+                    };
+
+                    let datasink = DataSink::from_instance(&element);
+                    Some(datasink.pull(timeout).to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_NEW_DATA,
+                    &[],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_SYNC_ACQUIRED,
+                    &[u32::static_type().into(), u32::static_type().into()],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_DATA_REJECTED,
+                    &[u32::static_type().into(), String::static_type().into()],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+            ]
         });
 
         SIGNALS.as_ref()
@@ -75,12 +1326,19 @@ impl ElementImpl for DataSink {
                         "format",
                         &gst::List::new(&[
                             &gst_video::VideoFormat::Uyvy.to_str(),
+                            &gst_video::VideoFormat::Yuy2.to_str(),
+                            &gst_video::VideoFormat::I420.to_str(),
+                            &gst_video::VideoFormat::Nv12.to_str(),
                             &gst_video::VideoFormat::Argb.to_str(),
                             &gst_video::VideoFormat::Bgra.to_str(),
+                            &gst_video::VideoFormat::Rgba.to_str(),
+                            &gst_video::VideoFormat::Bgrx.to_str(),
+                            &gst_video::VideoFormat::Gray8.to_str(),
+                            &gst_video::VideoFormat::V210.to_str(),
                         ]),
                     ),
-                    ("width", &gst::IntRange::<i32>::new(1920, i32::MAX)),
-                    ("height", &gst::IntRange::<i32>::new(1080, i32::MAX)),
+                    ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                    ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
                     (
                         "framerate",
                         &gst::FractionRange::new(
@@ -99,33 +1357,247 @@ impl ElementImpl for DataSink {
             )
             .unwrap();
 
-            vec![sink_pad_template]
+            // Data of a single channel, named after the channel number
+            let src_pad_template = gst::PadTemplate::new(
+                "src_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &gst::Caps::builder("text/x-raw")
+                    .field("format", &"utf8")
+                    .build(),
+            )
+            .unwrap();
+
+            // KLV packets of every channel
+            let klv_pad_template = gst::PadTemplate::new(
+                "klv",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &gst::Caps::builder("meta/x-klv")
+                    .field("parsed", &true)
+                    .build(),
+            )
+            .unwrap();
+
+            // Captions of every channel, with the caps of their caption type
+            let caption_pad_template = gst::PadTemplate::new(
+                "captions",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &caption_src_caps(),
+            )
+            .unwrap();
+
+            // Every frame as ST 2038 packets, for `mpegtsmux`
+            let st2038_pad_template = gst::PadTemplate::new(
+                "st2038",
+                gst::PadDirection::Src,
+                gst::PadPresence::Request,
+                &gst::Caps::builder("meta/x-st-2038")
+                    .field("alignment", &"frame")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![
+                sink_pad_template,
+                src_pad_template,
+                klv_pad_template,
+                caption_pad_template,
+                st2038_pad_template,
+            ]
         });
         PAD_TEMPLATES.as_ref()
     }
+
+    fn request_new_pad(
+        &self,
+        element: &Self::Type,
+        templ: &gst::PadTemplate,
+        name: Option<String>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let mut src_pads = self.src_pads.lock().unwrap();
+
+        // KLV and captions of every channel and ST 2038 each go out on a single pad
+        let name_template = templ.name_template();
+        let content = match name_template.as_str() {
+            "klv" => Some(PadContent::Records(RecordType::Klv)),
+            "captions" => Some(PadContent::Records(RecordType::Captions)),
+            "st2038" => Some(PadContent::St2038),
+            _ => None,
+        };
+        if let Some(content) = content {
+            if src_pads.pads.iter().any(|pad| pad.content == content) {
+                gst_error!(CAT, obj: element, "There is a {} pad already", name_template);
+                return None;
+            }
+
+            let pad = gst::Pad::from_template(templ, Some(name_template.as_str()));
+            src_pads.pads.push(ChannelPad {
+                pad: pad.clone(),
+                channel: None,
+                content,
+                needs_initial_events: true,
+                caps: None,
+            });
+            drop(src_pads);
+
+            gst_debug!(CAT, obj: element, "Adding {} pad", name_template);
+
+            pad.set_active(true).unwrap();
+            element.add_pad(&pad).unwrap();
+
+            return Some(pad);
+        }
+
+        let is_taken = |channel: u8| {
+            src_pads.pads.iter().any(|pad| {
+                pad.content == PadContent::Records(RecordType::Data) && pad.channel == Some(channel)
+            })
+        };
+
+        let channel = match name.as_deref().and_then(|name| name.strip_prefix("src_")) {
+            Some(channel) => match channel.parse::<u8>() {
+                Ok(channel) => channel,
+                Err(err) => {
+                    gst_error!(CAT, obj: element, "Invalid pad name {:?}: {}", name, err);
+                    return None;
+                }
+            },
+            None => match (0..=u8::MAX).find(|channel| !is_taken(*channel)) {
+                Some(channel) => channel,
+                None => {
+                    gst_error!(CAT, obj: element, "All channels have a pad already");
+                    return None;
+                }
+            },
+        };
+
+        if is_taken(channel) {
+            gst_error!(CAT, obj: element, "Channel {} has a pad already", channel);
+            return None;
+        }
+
+        let pad = gst::Pad::from_template(templ, Some(&format!("src_{}", channel)));
+        src_pads.pads.push(ChannelPad {
+            pad: pad.clone(),
+            channel: Some(channel),
+            content: PadContent::Records(RecordType::Data),
+            needs_initial_events: true,
+            caps: None,
+        });
+        drop(src_pads);
+
+        gst_debug!(CAT, obj: element, "Adding pad for channel {}", channel);
+
+        pad.set_active(true).unwrap();
+        element.add_pad(&pad).unwrap();
+
+        Some(pad)
+    }
+
+    fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
+        gst_debug!(CAT, obj: element, "Releasing pad {}", pad.name());
+
+        self.src_pads
+            .lock()
+            .unwrap()
+            .pads
+            .retain(|channel_pad| &channel_pad.pad != pad);
+
+        let _ = pad.set_active(false);
+        let _ = element.remove_pad(pad);
+    }
 }
 
 impl BaseSinkImpl for DataSink {
     // Called when starting, so we can initialize all stream-related state to its defaults
     fn start(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        let keys = load_keys(&settings.key, settings.key_file.as_deref())?;
+        let dictionary = match settings.compression_dictionary {
+            Some(ref path) => fs::read(path).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Failed to read compression dictionary {}: {}", path, err]
+                )
+            })?,
+            None => Vec::new(),
+        };
+
+        *self.state.lock().unwrap() = State {
+            keys,
+            dictionary: Arc::new(dictionary),
+            ..Default::default()
+        };
+
+        self.set_flushing(false, true);
+
         gst_info!(CAT, obj: element, "Started");
         Ok(())
     }
 
     // Called when shutting down the element so we can release all stream-related state
     fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        self.set_flushing(true, true);
+
         gst_info!(CAT, obj: element, "Stopped");
 
         Ok(())
     }
 
+    // Releases a streaming thread blocked on a full buffer
+    fn unlock(&self, _element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        self.set_flushing(true, false);
+        Ok(())
+    }
+
+    fn unlock_stop(&self, _element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        self.set_flushing(false, false);
+        Ok(())
+    }
+
     fn set_caps(&self, element: &Self::Type, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(caps).map_err(|_| {
+            gst::loggable_error!(CAT, "Failed to build `VideoInfo` from caps {}", caps)
+        })?;
+
         gst_debug!(CAT, obj: element, "Configuring for caps {}", caps);
 
+        let mut state = self.state.lock().unwrap();
+        state.info = Some(info);
+        state.sync = None;
+        drop(state);
+
         let _ = element.post_message(gst::message::Latency::builder().src(element).build());
 
         Ok(())
     }
+
+    fn event(&self, element: &Self::Type, event: gst::Event) -> bool {
+        match event.view() {
+            gst::EventView::Segment(..) => {
+                self.src_pads.lock().unwrap().segment = Some(event.clone());
+                self.forward_event(element, &event);
+            }
+            gst::EventView::Eos(..) => {
+                self.received.lock().unwrap().eos = true;
+                self.received_changed.notify_all();
+                self.forward_event(element, &event);
+            }
+            gst::EventView::FlushStart(..) => {
+                self.forward_event(element, &event);
+            }
+            gst::EventView::FlushStop(..) => {
+                self.set_flushing(false, true);
+                self.forward_event(element, &event);
+            }
+            _ => (),
+        }
+
+        self.parent_event(element, event)
+    }
 }
 
 impl VideoSinkImpl for DataSink {
@@ -136,29 +1608,760 @@ impl VideoSinkImpl for DataSink {
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
         let map = buffer.map_readable().unwrap();
 
-        let mut data = map.as_slice();
+        let settings = self.settings.lock().unwrap().clone();
+        let info = self.state.lock().unwrap().info.clone();
+
+        // 8 bit formats can carry frames as levels after a training sequence,
+        // otherwise they are in the picture as is, or in the luma of v210
+        let carried = match &info {
+            Some(info) if crate::levels::supports(info) => {
+                crate::levels::read_levels(info, map.as_slice())
+            }
+            _ => None,
+        };
+        let luma = match &info {
+            Some(info) if info.format() == gst_video::VideoFormat::V210 => {
+                Some(crate::lines::read_v210(info, map.as_slice()))
+            }
+            _ => None,
+        };
+        let frame = carried
+            .as_deref()
+            .or_else(|| luma.as_deref())
+            .unwrap_or_else(|| map.as_slice());
 
-        let number = data.get_u32();
+        // Levels are found by their training sequence already
+        let decoded = match &info {
+            Some(info) if settings.sync_search && carried.is_none() => {
+                self.sync(element, &settings, info, frame)
+            }
+            Some(info) if settings.line_margin > 0 && carried.is_none() => {
+                let position = SyncPosition { line: 0, column: 0 };
+                match frame_data_at(&settings, info, frame, position) {
+                    Some(data) => {
+                        decode_at(&data, 0).map(|decoded| decoded.map(|decoded| (decoded, data)))
+                    }
+                    None => Ok(None),
+                }
+            }
+            _ => decode_at(frame, 0)
+                .map(|decoded| decoded.map(|decoded| (decoded, Cow::Borrowed(frame)))),
+        };
+        let (decoded, frame) = match decoded {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => return Ok(gst::FlowSuccess::Ok),
+            Err(err) => {
+                gst_warning!(CAT, obj: element, "{}", err);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+        let header = decoded.header;
 
-        if number != MAGIC_NUMBER {
-            return Ok(gst::FlowSuccess::Ok);
+        let fps = info.as_ref().map(|info| info.fps());
+        let (keys, dictionary) = {
+            let state = self.state.lock().unwrap();
+            (state.keys.clone(), state.dictionary.clone())
+        };
+        let video_time_code = header
+            .timecode
+            .zip(fps)
+            .and_then(|(timecode, fps)| to_video_time_code(timecode, fps));
+        let timecode = header.timecode.map(|timecode| timecode.to_string());
+
+        // The frame as it came, records are not checked or decoded
+        if let Some(info) = &info {
+            self.push_st2038(
+                element,
+                &frame[..decoded.len],
+                info,
+                buffer,
+                video_time_code.as_ref(),
+            );
         }
 
-        let length = convert_back_with_0_and_255(data.get_u32()) as usize;
+        let mut signed = Vec::new();
+        for record in decoded.records() {
+            let record_type = match record.record_type() {
+                Some(record_type) => record_type,
+                None => {
+                    gst_debug!(
+                        CAT,
+                        obj: element,
+                        "Skipping record of unknown type on channel {}",
+                        record.channel
+                    );
+                    continue;
+                }
+            };
+            let data = match self.decode_record(
+                decoded.header_bytes(),
+                &record,
+                &keys,
+                &dictionary,
+                &settings,
+                &mut signed,
+            ) {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    gst_debug!(
+                        CAT,
+                        obj: element,
+                        "Skipping record on channel {} of a repeated frame",
+                        record.channel
+                    );
+                    continue;
+                }
+                Err((error, reason)) => {
+                    self.reject(element, record.channel, error, &reason);
+                    continue;
+                }
+            };
+            let record = Record {
+                channel: record.channel,
+                flags: record_type.flags(),
+                data: &data,
+            };
+
+            if record_type == RecordType::Klv {
+                if let Err(err) = check_klv(record.data, settings.klv_checksum) {
+                    self.reject(
+                        element,
+                        record.channel,
+                        gst::StreamError::Decode,
+                        &err.to_string(),
+                    );
+                    continue;
+                }
+            }
+
+            // Goes out on every pad, ahead of the buffers of this frame
+            if record_type == RecordType::Scte35 {
+                let event = scte35::check_splice_info_section(record.data)
+                    .map_err(|err| err.to_string())
+                    .and_then(|()| {
+                        new_scte35_event(record.data)
+                            .ok_or_else(|| "libgstmpegts can't parse section".to_string())
+                    });
+                match event {
+                    Ok(event) => self.forward_event(element, &event),
+                    Err(reason) => {
+                        self.reject(element, record.channel, gst::StreamError::Decode, &reason)
+                    }
+                }
+                continue;
+            }
+
+            // Captions go out without their caption type, which goes in the caps
+            let (record, caps) = if record_type == RecordType::Captions {
+                match unpack_captions(record.data) {
+                    Some((caption_type, data)) => (
+                        Record {
+                            channel: record.channel,
+                            flags: record.flags,
+                            data,
+                        },
+                        Some(caption_caps(caption_type)),
+                    ),
+                    None => {
+                        self.reject(
+                            element,
+                            record.channel,
+                            gst::StreamError::Decode,
+                            "Invalid captions",
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                (record, None)
+            };
+
+            self.push_to_channel_pads(
+                element,
+                record_type,
+                &record,
+                caps.as_ref(),
+                buffer,
+                video_time_code.as_ref(),
+            );
+
+            // Only data, structures and variants are signalled
+            if !matches!(
+                record_type,
+                RecordType::Data | RecordType::Structure | RecordType::Variant
+            ) {
+                continue;
+            }
+
+            if !settings.channels.is_empty() && !settings.channels.contains(&record.channel) {
+                continue;
+            }
+
+            if record_type == RecordType::Structure {
+                let structure = std::str::from_utf8(record.data)
+                    .ok()
+                    .and_then(|structure| structure.parse::<gst::Structure>().ok());
+                match structure {
+                    Some(structure) => {
+                        if settings.emit_signals {
+                            element
+                                .emit_by_name(
+                                    SIGNAL_STRUCTURE_RECEIVED,
+                                    &[&structure, &(record.channel as u32), &timecode],
+                                )
+                                .unwrap();
+                        }
+                        let message = received_message(
+                            "structure",
+                            structure.to_send_value(),
+                            record.channel,
+                            timecode.as_deref(),
+                        );
+                        self.buffer_received(element, message);
+                    }
+                    None => self.reject(
+                        element,
+                        record.channel,
+                        gst::StreamError::Decode,
+                        "Invalid structure",
+                    ),
+                }
+                continue;
+            }
+
+            if record_type == RecordType::Variant {
+                match variant_from_bytes(record.data) {
+                    Some(variant) => {
+                        if settings.emit_signals {
+                            element
+                                .emit_by_name(
+                                    SIGNAL_VARIANT_RECEIVED,
+                                    &[&variant, &(record.channel as u32), &timecode],
+                                )
+                                .unwrap();
+                        }
+                        let message = received_message(
+                            "variant",
+                            variant.to_send_value(),
+                            record.channel,
+                            timecode.as_deref(),
+                        );
+                        self.buffer_received(element, message);
+                    }
+                    None => self.reject(
+                        element,
+                        record.channel,
+                        gst::StreamError::Decode,
+                        "Invalid variant",
+                    ),
+                }
+                continue;
+            }
+
+            let content = match std::str::from_utf8(record.data) {
+                Ok(content) => content.to_string(),
+                Err(err) => {
+                    gst_warning!(CAT, obj: element, "Dropping non UTF-8 content: {}", err);
+                    continue;
+                }
+            };
+
+            gst_info!(
+                CAT,
+                obj: element,
+                "Got content {:?} on channel {}",
+                content,
+                record.channel
+            );
 
-        if length > buffer.size() || length == 0 {
-            return Ok(gst::FlowSuccess::Ok);
+            if settings.emit_signals {
+                element
+                    .emit_by_name(SIGNAL_DATA_RECEIVED, &[&content])
+                    .unwrap();
+                element
+                    .emit_by_name(
+                        SIGNAL_DATA_RECEIVED_ON_CHANNEL,
+                        &[&content, &(record.channel as u32), &timecode],
+                    )
+                    .unwrap();
+            }
+            let message = received_message(
+                "data",
+                content.to_send_value(),
+                record.channel,
+                timecode.as_deref(),
+            );
+            self.buffer_received(element, message);
         }
 
-        let raw_content = convert_from_sdi_safe_payload(&data[..length]);
-        let content = std::str::from_utf8(&raw_content).unwrap().to_string();
+        if !signed.is_empty() {
+            self.state.lock().unwrap().last_signed = signed;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::sync::Once;
+
+    use crate::encoding::{FrameEncoder, FrameHeader, PayloadCoding};
 
-        gst_info!(CAT, obj: element, "Got content {:?}", content);
+    const KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const OTHER_KEY: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+    const CAPS: &str = "video/x-raw,format=UYVY,width=320,height=240,framerate=25/1";
 
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            gst::init().unwrap();
+            crate::plugin_register_static().unwrap();
+        });
+    }
+
+    fn video_info(format: gst_video::VideoFormat, width: u32, height: u32) -> gst_video::VideoInfo {
+        gst_video::VideoInfo::builder(format, width, height)
+            .build()
+            .unwrap()
+    }
+
+    /// Picture of `info` with a frame holding `record`, written like `datasrc`
+    /// does with `line_margin`. v210 pictures are read back into their plane of
+    /// luma bytes, as `datasink` searches them.
+    fn picture_with(info: &gst_video::VideoInfo, line_margin: u32, record: &Record) -> Vec<u8> {
+        let v210 = info.format() == gst_video::VideoFormat::V210;
+        let layout = if line_margin == 0 && !v210 {
+            FrameLayout::new(info.size())
+        } else {
+            crate::lines::layout(info, line_margin)
+        };
+        let header = FrameHeader {
+            timecode: None,
+            crc: true,
+        };
+        let mut encoder = FrameEncoder::with_coding(layout, header, PayloadCoding::Escaped);
+        assert!(encoder.push(record));
+
+        if v210 {
+            let mut luma = crate::lines::blank_v210(info);
+            encoder.encode(&mut luma).unwrap();
+            let mut picture = vec![0; info.size()];
+            crate::lines::write_v210(info, &luma, &mut picture);
+            return crate::lines::read_v210(info, &picture);
+        }
+
+        let mut picture = vec![0; info.size()];
+        encoder.encode(&mut picture).unwrap();
+        picture
+    }
+
+    /// `picture` moved down by `lines` and right by `columns` pixels, what goes past
+    /// the right edge is lost
+    fn shift(info: &gst_video::VideoInfo, picture: &[u8], lines: u32, columns: u32) -> Vec<u8> {
+        let stride = crate::lines::stride(info);
+        let offset = crate::lines::column_offset(info, columns).unwrap();
+        let width = crate::lines::layout(info, 0).line_width;
+
+        let mut shifted = vec![0; picture.len()];
+        for (row, line) in picture
+            .chunks(stride)
+            .take((info.height() - lines) as usize)
+            .enumerate()
+        {
+            let start = (row + lines as usize) * stride;
+            shifted[start + offset..start + width].copy_from_slice(&line[..width - offset]);
+        }
+        shifted
+    }
+
+    /// Pipeline of `description` with its `src` and `sink` elements
+    fn pipeline(description: &str) -> (gst::Pipeline, gst::Element, gst::Element) {
+        let pipeline = gst::parse_launch(description)
+            .unwrap()
+            .downcast::<gst::Pipeline>()
+            .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+        let sink = pipeline.by_name("sink").unwrap();
+        (pipeline, src, sink)
+    }
+
+    /// Plays `pipeline` until EOS. It is left there, so what `datasink` buffered
+    /// can still be pulled.
+    fn play_to_eos(pipeline: &gst::Pipeline) {
+        pipeline.set_state(gst::State::Playing).unwrap();
+        let message = pipeline.bus().unwrap().timed_pop_filtered(
+            gst::ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        let error = match message.as_ref().map(|message| message.view()) {
+            Some(gst::MessageView::Eos(..)) => return,
+            Some(gst::MessageView::Error(err)) => err.error().to_string(),
+            _ => "Timed out".to_string(),
+        };
+        pipeline.set_state(gst::State::Null).unwrap();
+        panic!("{}", error);
+    }
+
+    /// Arguments of every emission of `signal` by `element`, as `read` takes them
+    fn collect<T: Send + 'static>(
+        element: &gst::Element,
+        signal: &str,
+        read: impl Fn(&[glib::Value]) -> T + Send + Sync + 'static,
+    ) -> mpsc::Receiver<T> {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
         element
-            .emit_by_name(SIGNAL_DATA_RECEIVED, &[&content])
+            .connect(signal, false, move |args| {
+                sender.lock().unwrap().send(read(args)).unwrap();
+                None
+            })
             .unwrap();
+        receiver
+    }
 
-        Ok(gst::FlowSuccess::Ok)
+    fn data_received(element: &gst::Element) -> mpsc::Receiver<(String, u32, String)> {
+        collect(element, "data-received-on-channel", |args| {
+            (
+                args[1].get::<String>().unwrap(),
+                args[2].get::<u32>().unwrap(),
+                args[3].get::<String>().unwrap(),
+            )
+        })
+    }
+
+    fn data_rejected(element: &gst::Element) -> mpsc::Receiver<(u32, String)> {
+        collect(element, "data-rejected", |args| {
+            (
+                args[1].get::<u32>().unwrap(),
+                args[2].get::<String>().unwrap(),
+            )
+        })
+    }
+
+    fn send_data(src: &gst::Element, channel: u32, data: &str) {
+        let accepted = src
+            .emit_by_name("send-data-on-channel", &[&channel, &data])
+            .unwrap()
+            .unwrap()
+            .get::<bool>()
+            .unwrap();
+        assert!(accepted);
+    }
+
+    fn count(element: &gst::Element, property: &str) -> u64 {
+        element.property(property).unwrap().get::<u64>().unwrap()
+    }
+
+    #[test]
+    fn channels_are_filtered() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=3 ! {} ! datasink name=sink channels=1,3",
+            CAPS
+        ));
+        let received = data_received(&sink);
+        // Handlers written before channels only get the data
+        let received_data = collect(&sink, "data-received", |args| {
+            assert_eq!(args.len(), 2);
+            args[1].get::<String>().unwrap()
+        });
+
+        send_data(&src, 1, "one");
+        send_data(&src, 2, "two");
+        send_data(&src, 3, "three");
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert_eq!(
+            received
+                .try_iter()
+                .map(|(data, channel, _)| (data, channel))
+                .collect::<Vec<_>>(),
+            [("one".to_string(), 1), ("three".to_string(), 3)]
+        );
+        assert_eq!(
+            received_data.try_iter().collect::<Vec<_>>(),
+            ["one", "three"]
+        );
+    }
+
+    #[test]
+    fn timecodes_are_signalled() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=3 timecode-start=01:02:03:04 ! {} \
+             ! datasink name=sink",
+            CAPS
+        ));
+        let received = data_received(&sink);
+
+        send_data(&src, 0, "Hello");
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        // The timecode of the frame it came in
+        assert_eq!(
+            received.try_iter().collect::<Vec<_>>(),
+            [("Hello".to_string(), 0, "01:02:03:04".to_string())]
+        );
+    }
+
+    #[test]
+    fn signed_records_with_another_key_are_rejected() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=3 key={} key-mode=sign ! {} \
+             ! datasink name=sink key={}",
+            KEY, CAPS, OTHER_KEY
+        ));
+        let received = data_received(&sink);
+        let rejected = data_rejected(&sink);
+
+        send_data(&src, 2, "Hello");
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert_eq!(received.try_iter().count(), 0);
+        let rejected: Vec<_> = rejected.try_iter().collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, 2);
+        assert!(!rejected[0].1.is_empty());
+        assert_eq!(count(&sink, "rejected"), 1);
+        assert_eq!(count(&sink, "replayed"), 0);
+    }
+
+    #[test]
+    fn repeated_frames_are_only_counted() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=5 key={} key-mode=sign ! {} \
+             ! dataimpair repeat-probability=1 ! datasink name=sink key={}",
+            KEY, CAPS, KEY
+        ));
+        let received = data_received(&sink);
+        let rejected = data_rejected(&sink);
+
+        send_data(&src, 0, "Hello");
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        // Every frame is the first one again
+        assert_eq!(
+            received
+                .try_iter()
+                .map(|(data, _, _)| data)
+                .collect::<Vec<_>>(),
+            ["Hello"]
+        );
+        assert_eq!(rejected.try_iter().count(), 0);
+        assert_eq!(count(&sink, "rejected"), 0);
+        assert_eq!(count(&sink, "replayed"), 4);
+    }
+
+    #[test]
+    fn pull_data_returns_buffered_messages() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=3 ! {} \
+             ! datasink name=sink emit-signals=false max-buffered=10",
+            CAPS
+        ));
+        let received = data_received(&sink);
+
+        send_data(&src, 0, "Hello");
+        send_data(&src, 5, "World");
+        play_to_eos(&pipeline);
+
+        let pull = || {
+            sink.emit_by_name("pull-data", &[&0u64])
+                .unwrap()
+                .unwrap()
+                .get::<Option<gst::Structure>>()
+                .unwrap()
+        };
+        assert_eq!(sink.property("buffered").unwrap().get::<u32>().unwrap(), 2);
+        for &(data, channel) in &[("Hello", 0), ("World", 5)] {
+            let message = pull().unwrap();
+            assert_eq!(message.name(), "data");
+            assert_eq!(message.get::<String>("data").unwrap(), data);
+            assert_eq!(message.get::<u32>("channel").unwrap(), channel);
+            assert_eq!(message.get::<String>("timecode").unwrap(), "00:00:00:00");
+        }
+        // Nothing more comes after EOS
+        assert!(pull().is_none());
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert_eq!(received.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn sync_search_follows_shifted_frames() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(&format!(
+            "datasrc name=src num-buffers=5 schedule-format=pts line-margin=12 ! {} \
+             ! datasink name=sink sync-search=true line-margin=12",
+            CAPS
+        ));
+        let received = data_received(&sink);
+        let acquired = collect(&sink, "sync-acquired", |args| {
+            (args[1].get::<u32>().unwrap(), args[2].get::<u32>().unwrap())
+        });
+
+        // The picture moves further after the third frame
+        let info = video_info(gst_video::VideoFormat::Uyvy, 320, 240);
+        sink.static_pad("sink")
+            .unwrap()
+            .add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+                if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = probe_info.data {
+                    let (lines, columns) =
+                        if buffer.pts() < Some(gst::ClockTime::from_mseconds(100)) {
+                            (2, 6)
+                        } else {
+                            (4, 12)
+                        };
+                    let buffer = buffer.make_mut();
+                    let mut map = buffer.map_writable().unwrap();
+                    let shifted = shift(&info, map.as_slice(), lines, columns);
+                    map.as_mut_slice().copy_from_slice(&shifted);
+                }
+                gst::PadProbeReturn::Ok
+            })
+            .unwrap();
+
+        send_data(&src, 0, "First");
+        let accepted = src
+            .emit_by_name("send-data-at", &[&0u32, &"Second", &120_000_000u64])
+            .unwrap()
+            .unwrap()
+            .get::<bool>()
+            .unwrap();
+        assert!(accepted);
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert_eq!(
+            received
+                .try_iter()
+                .map(|(data, _, _)| data)
+                .collect::<Vec<_>>(),
+            ["First", "Second"]
+        );
+        // Frames without data in between keep the lock
+        assert_eq!(acquired.try_iter().collect::<Vec<_>>(), [(2, 6), (4, 12)]);
+    }
+
+    #[test]
+    fn v210_carries_frames_in_luma() {
+        init();
+
+        let (pipeline, src, sink) = pipeline(
+            "datasrc name=src num-buffers=3 \
+             ! video/x-raw,format=v210,width=320,height=240,framerate=25/1 \
+             ! datasink name=sink",
+        );
+        let received = data_received(&sink);
+
+        // Whether every sample of a picture is one SDI can carry
+        let info = video_info(gst_video::VideoFormat::V210, 320, 240);
+        let (sender, carried) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        sink.static_pad("sink")
+            .unwrap()
+            .add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+                    let map = buffer.map_readable().unwrap();
+                    let legal = map
+                        .chunks(info.stride()[0] as usize)
+                        // Blocks of 6 pixels, the padding to 48 isn't sent
+                        .flat_map(|line| line[..(320 + 5) / 6 * 16].chunks_exact(4))
+                        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                        .all(|word| {
+                            word >> 30 == 0
+                                && [word & 0x3ff, word >> 10 & 0x3ff, word >> 20 & 0x3ff]
+                                    .iter()
+                                    .all(|sample| (0x004..0x3fc).contains(sample))
+                        });
+                    sender.lock().unwrap().send(legal).unwrap();
+                }
+                gst::PadProbeReturn::Ok
+            })
+            .unwrap();
+
+        send_data(&src, 2, "Hello v210");
+        play_to_eos(&pipeline);
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert_eq!(
+            received.try_iter().collect::<Vec<_>>(),
+            [("Hello v210".to_string(), 2, "00:00:00:00".to_string())]
+        );
+        assert_eq!(carried.try_iter().collect::<Vec<_>>(), [true; 3]);
+    }
+
+    #[test]
+    fn shifted_frames_round_trip() {
+        init();
+
+        let data: Vec<u8> = (0..600).map(|i| (i % 251) as u8).collect();
+        let record = Record {
+            channel: 4,
+            flags: 0,
+            data: &data,
+        };
+
+        for &format in &[gst_video::VideoFormat::Uyvy, gst_video::VideoFormat::V210] {
+            let info = video_info(format, 96, 32);
+            let settings = Settings {
+                sync_search: true,
+                line_margin: 12,
+                ..Settings::default()
+            };
+
+            let picture = picture_with(&info, settings.line_margin, &record);
+            let position = SyncPosition { line: 0, column: 0 };
+            let data = frame_data_at(&settings, &info, &picture, position).unwrap();
+            let decoded = decode_at(&data, 0).unwrap().unwrap();
+            assert_eq!(decoded.records(), [record]);
+
+            // Frames span several lines, each of them is read from the new column
+            let shifted = shift(&info, &picture, 3, 6);
+            let (position, decoded, _) = search_frame(&settings, &info, &shifted).unwrap();
+            assert_eq!(position, SyncPosition { line: 3, column: 6 });
+            assert_eq!(decoded.records(), [record]);
+
+            // Up to the whole margin
+            let shifted = shift(&info, &picture, 0, 12);
+            let (position, decoded, _) = search_frame(&settings, &info, &shifted).unwrap();
+            assert_eq!(
+                position,
+                SyncPosition {
+                    line: 0,
+                    column: 12
+                }
+            );
+            assert_eq!(decoded.records(), [record]);
+
+            // Without a margin the ends of the lines are lost, which the CRC catches
+            let settings = Settings {
+                line_margin: 0,
+                ..settings
+            };
+            let picture = picture_with(&info, 0, &record);
+            let shifted = shift(&info, &picture, 0, 6);
+            assert!(search_frame(&settings, &info, &shifted).is_none());
+            let position = SyncPosition { line: 0, column: 6 };
+            let data = frame_data_at(&settings, &info, &shifted, position).unwrap();
+            assert_eq!(decode_at(&data, 0), Err(DecodeError::InvalidCrc));
+        }
     }
 }
@@ -2,22 +2,50 @@ use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst::ClockTime;
-use gst::{gst_debug, gst_info};
+use gst::{gst_debug, gst_error, gst_info, gst_log, gst_warning};
 use gst_base::prelude::*;
 use gst_base::subclass::prelude::*;
 
+use std::cmp::Ordering;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs;
 
-use std::sync::Mutex;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 use std::{i32, u32};
 
 use once_cell::sync::Lazy;
 
-use bytes::BufMut;
-
-use crate::encoding::convert_to_sdi_safe_payload;
-use crate::encoding::convert_without_0_and_255;
-use crate::encoding::MAGIC_NUMBER;
+use crate::captions::caption_sink_caps;
+use crate::captions::from_video_caption_type;
+use crate::encoding::captions::pack_captions;
+use crate::encoding::compress;
+use crate::encoding::klv;
+use crate::encoding::levels;
+use crate::encoding::scte35;
+use crate::encoding::st2038;
+use crate::encoding::unix_time_us;
+use crate::encoding::Compression;
+use crate::encoding::FrameDecoder;
+use crate::encoding::FrameEncoder;
+use crate::encoding::FrameHeader;
+use crate::encoding::FrameLayout;
+use crate::encoding::Keys;
+use crate::encoding::PayloadCoding;
+use crate::encoding::Record;
+use crate::encoding::RecordType;
+use crate::encoding::Timecode;
+use crate::encoding::ENCRYPTION_OVERHEAD;
+use crate::encoding::RECORD_FLAG_COMPRESSED;
+use crate::encoding::RECORD_FLAG_ENCRYPTED;
+use crate::encoding::RECORD_FLAG_SIGNED;
+use crate::encoding::SIGNING_OVERHEAD;
+use crate::keys::load_keys;
+use crate::mpegts::parse_scte35_event;
+use crate::timecode::from_video_time_code;
+use crate::timecode::to_video_time_code;
+use crate::variant::variant_to_bytes;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -27,9 +55,715 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
+const SIGNAL_NEED_DATA: &str = "need-data";
+const SIGNAL_ENOUGH_DATA: &str = "enough-data";
+const SIGNAL_DATA_LATE: &str = "data-late";
+
+/// Channel used by `send-data`
+const DEFAULT_CHANNEL: u8 = 0;
+
+const DEFAULT_MAX_QUEUED_MESSAGES: u32 = 200;
+const DEFAULT_MAX_QUEUED_BYTES: u64 = 4 * 1024 * 1024;
+const DEFAULT_LEAKY_TYPE: LeakyType = LeakyType::DropOldest;
+const DEFAULT_SCHEDULE_FORMAT: ScheduleFormat = ScheduleFormat::RunningTime;
+const DEFAULT_TIMECODE_START: Timecode = Timecode {
+    hours: 0,
+    minutes: 0,
+    seconds: 0,
+    frames: 0,
+};
+const DEFAULT_KEY_ID: u32 = 0;
+const DEFAULT_KEY_MODE: KeyMode = KeyMode::Encrypt;
+const DEFAULT_COMPRESSION: CompressionType = CompressionType::None;
+const DEFAULT_PAYLOAD_CODING: PayloadCodingType = PayloadCodingType::Escaped;
+const DEFAULT_CARRIAGE: Carriage = Carriage::Picture;
+const DEFAULT_LINE_MARGIN: u32 = 0;
+const DEFAULT_CRC: bool = true;
+// Type 2 packets for user applications
+const DEFAULT_ANC_DID: u32 = 0x50;
+const DEFAULT_ANC_SDID: u32 = 0x01;
+const DEFAULT_MODE: Mode = Mode::Hd1080p25;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcLeakyType")]
+pub enum LeakyType {
+    #[genum(
+        name = "Not leaky, keep queueing above the limits and only signal enough-data",
+        nick = "none"
+    )]
+    None = 0,
+    #[genum(name = "Drop the newest message", nick = "drop-newest")]
+    DropNewest = 1,
+    #[genum(name = "Drop the oldest message", nick = "drop-oldest")]
+    DropOldest = 2,
+    #[genum(
+        name = "Block send-data until there is room, except on the streaming thread",
+        nick = "block"
+    )]
+    Block = 3,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcScheduleFormat")]
+pub enum ScheduleFormat {
+    #[genum(name = "Running time of the frame", nick = "running-time")]
+    RunningTime = 0,
+    #[genum(name = "PTS of the frame", nick = "pts")]
+    Pts = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcKeyMode")]
+pub enum KeyMode {
+    #[genum(name = "Encrypt messages with AES-256-GCM", nick = "encrypt")]
+    Encrypt = 0,
+    #[genum(
+        name = "Sign messages with HMAC-SHA256 and a timestamp, without encrypting them",
+        nick = "sign"
+    )]
+    Sign = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcCompression")]
+pub enum CompressionType {
+    #[genum(name = "Send messages as they are", nick = "none")]
+    None = 0,
+    #[genum(name = "Deflate", nick = "deflate")]
+    Deflate = 1,
+    #[genum(name = "Zstandard", nick = "zstd")]
+    Zstd = 2,
+    #[genum(name = "LZ4", nick = "lz4")]
+    Lz4 = 3,
+}
+
+impl CompressionType {
+    fn compression(self) -> Option<Compression> {
+        match self {
+            CompressionType::None => None,
+            CompressionType::Deflate => Some(Compression::Deflate),
+            CompressionType::Zstd => Some(Compression::Zstd),
+            CompressionType::Lz4 => Some(Compression::Lz4),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcPayloadCoding")]
+pub enum PayloadCodingType {
+    #[genum(
+        name = "Escape reserved bytes, up to twice the size, works with every datasink",
+        nick = "escaped"
+    )]
+    Escaped = 0,
+    #[genum(
+        name = "Stuff reserved bytes, under 1% larger, needs an up to date datasink",
+        nick = "stuffed"
+    )]
+    Stuffed = 1,
+}
+
+impl PayloadCodingType {
+    fn coding(self) -> PayloadCoding {
+        match self {
+            PayloadCodingType::Escaped => PayloadCoding::Escaped,
+            PayloadCodingType::Stuffed => PayloadCoding::Stuffed,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcCarriage")]
+pub enum Carriage {
+    #[genum(name = "Write frames into the picture", nick = "picture")]
+    Picture = 0,
+    #[genum(
+        name = "Write frames as grey levels after a training sequence, survives colour \
+                space conversion",
+        nick = "levels"
+    )]
+    Levels = 1,
+}
+
+impl Carriage {
+    /// Video formats frames can be carried in
+    fn formats(self) -> &'static [gst_video::VideoFormat] {
+        match self {
+            Carriage::Picture => &[
+                gst_video::VideoFormat::Uyvy,
+                gst_video::VideoFormat::Yuy2,
+                gst_video::VideoFormat::I420,
+                gst_video::VideoFormat::Nv12,
+                gst_video::VideoFormat::Argb,
+                gst_video::VideoFormat::Bgra,
+                gst_video::VideoFormat::Rgba,
+                gst_video::VideoFormat::Bgrx,
+                gst_video::VideoFormat::Gray8,
+                gst_video::VideoFormat::V210,
+            ],
+            // 8 bit formats only
+            Carriage::Levels => &[
+                gst_video::VideoFormat::Uyvy,
+                gst_video::VideoFormat::Yuy2,
+                gst_video::VideoFormat::I420,
+                gst_video::VideoFormat::Nv12,
+                gst_video::VideoFormat::Argb,
+                gst_video::VideoFormat::Bgra,
+                gst_video::VideoFormat::Rgba,
+                gst_video::VideoFormat::Bgrx,
+                gst_video::VideoFormat::Gray8,
+            ],
+        }
+    }
+}
+
+/// Video mode caps are fixated to where downstream leaves a choice
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstDataSrcMode")]
+pub enum Mode {
+    #[genum(name = "NTSC SD 60i", nick = "ntsc")]
+    Ntsc = 0,
+    #[genum(name = "PAL SD 50i", nick = "pal")]
+    Pal = 1,
+    #[genum(name = "HD720 50p", nick = "720p50")]
+    Hd720p50 = 2,
+    #[genum(name = "HD720 59.94p", nick = "720p5994")]
+    Hd720p5994 = 3,
+    #[genum(name = "HD720 60p", nick = "720p60")]
+    Hd720p60 = 4,
+    #[genum(name = "HD1080 50i", nick = "1080i50")]
+    Hd1080i50 = 5,
+    #[genum(name = "HD1080 59.94i", nick = "1080i5994")]
+    Hd1080i5994 = 6,
+    #[genum(name = "HD1080 60i", nick = "1080i60")]
+    Hd1080i60 = 7,
+    #[genum(name = "HD1080 23.98p", nick = "1080p2398")]
+    Hd1080p2398 = 8,
+    #[genum(name = "HD1080 24p", nick = "1080p24")]
+    Hd1080p24 = 9,
+    #[genum(name = "HD1080 25p", nick = "1080p25")]
+    Hd1080p25 = 10,
+    #[genum(name = "HD1080 29.97p", nick = "1080p2997")]
+    Hd1080p2997 = 11,
+    #[genum(name = "HD1080 30p", nick = "1080p30")]
+    Hd1080p30 = 12,
+    #[genum(name = "HD1080 50p", nick = "1080p50")]
+    Hd1080p50 = 13,
+    #[genum(name = "HD1080 59.94p", nick = "1080p5994")]
+    Hd1080p5994 = 14,
+    #[genum(name = "HD1080 60p", nick = "1080p60")]
+    Hd1080p60 = 15,
+    #[genum(name = "UHD 2160 25p", nick = "2160p25")]
+    Uhd2160p25 = 16,
+    #[genum(name = "UHD 2160 29.97p", nick = "2160p2997")]
+    Uhd2160p2997 = 17,
+    #[genum(name = "UHD 2160 30p", nick = "2160p30")]
+    Uhd2160p30 = 18,
+    #[genum(name = "UHD 2160 50p", nick = "2160p50")]
+    Uhd2160p50 = 19,
+    #[genum(name = "UHD 2160 59.94p", nick = "2160p5994")]
+    Uhd2160p5994 = 20,
+    #[genum(name = "UHD 2160 60p", nick = "2160p60")]
+    Uhd2160p60 = 21,
+}
+
+/// Width, height, frame rate and whether a mode is interlaced
+struct ModeInfo {
+    width: i32,
+    height: i32,
+    fps: gst::Fraction,
+    interlaced: bool,
+}
+
+impl Mode {
+    fn info(self) -> ModeInfo {
+        let (width, height, fps_n, fps_d, interlaced) = match self {
+            Mode::Ntsc => (720, 486, 30000, 1001, true),
+            Mode::Pal => (720, 576, 25, 1, true),
+            Mode::Hd720p50 => (1280, 720, 50, 1, false),
+            Mode::Hd720p5994 => (1280, 720, 60000, 1001, false),
+            Mode::Hd720p60 => (1280, 720, 60, 1, false),
+            Mode::Hd1080i50 => (1920, 1080, 25, 1, true),
+            Mode::Hd1080i5994 => (1920, 1080, 30000, 1001, true),
+            Mode::Hd1080i60 => (1920, 1080, 30, 1, true),
+            Mode::Hd1080p2398 => (1920, 1080, 24000, 1001, false),
+            Mode::Hd1080p24 => (1920, 1080, 24, 1, false),
+            Mode::Hd1080p25 => (1920, 1080, 25, 1, false),
+            Mode::Hd1080p2997 => (1920, 1080, 30000, 1001, false),
+            Mode::Hd1080p30 => (1920, 1080, 30, 1, false),
+            Mode::Hd1080p50 => (1920, 1080, 50, 1, false),
+            Mode::Hd1080p5994 => (1920, 1080, 60000, 1001, false),
+            Mode::Hd1080p60 => (1920, 1080, 60, 1, false),
+            Mode::Uhd2160p25 => (3840, 2160, 25, 1, false),
+            Mode::Uhd2160p2997 => (3840, 2160, 30000, 1001, false),
+            Mode::Uhd2160p30 => (3840, 2160, 30, 1, false),
+            Mode::Uhd2160p50 => (3840, 2160, 50, 1, false),
+            Mode::Uhd2160p5994 => (3840, 2160, 60000, 1001, false),
+            Mode::Uhd2160p60 => (3840, 2160, 60, 1, false),
+        };
+
+        ModeInfo {
+            width,
+            height,
+            fps: gst::Fraction::new(fps_n, fps_d),
+            interlaced,
+        }
+    }
+}
+
+fn video_caps(formats: &[gst_video::VideoFormat]) -> gst::Caps {
+    let formats = formats
+        .iter()
+        .map(|format| format.to_str().to_send_value())
+        .collect();
+
+    gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            ("format", &gst::List::from_owned(formats)),
+            ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            (
+                "framerate",
+                &gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)),
+            ),
+            (
+                "interlace-mode",
+                &gst::List::new(&[&"progressive", &"interleaved"]),
+            ),
+        ],
+    )
+}
+
+#[derive(Clone)]
+struct Settings {
+    max_queued_messages: u32,
+    max_queued_bytes: u64,
+    leaky_type: LeakyType,
+    schedule_format: ScheduleFormat,
+    timecode_start: Timecode,
+    key: String,
+    key_file: Option<String>,
+    key_id: u8,
+    key_mode: KeyMode,
+    compression: CompressionType,
+    compression_dictionary: Option<String>,
+    payload_coding: PayloadCodingType,
+    carriage: Carriage,
+    // Frames are written line by line if not 0, see `lines`
+    line_margin: u32,
+    crc: bool,
+    mode: Mode,
+    anc_did: u8,
+    anc_sdid: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_queued_messages: DEFAULT_MAX_QUEUED_MESSAGES,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+            leaky_type: DEFAULT_LEAKY_TYPE,
+            schedule_format: DEFAULT_SCHEDULE_FORMAT,
+            timecode_start: DEFAULT_TIMECODE_START,
+            key: String::new(),
+            key_file: None,
+            key_id: DEFAULT_KEY_ID as u8,
+            key_mode: DEFAULT_KEY_MODE,
+            compression: DEFAULT_COMPRESSION,
+            compression_dictionary: None,
+            payload_coding: DEFAULT_PAYLOAD_CODING,
+            carriage: DEFAULT_CARRIAGE,
+            line_margin: DEFAULT_LINE_MARGIN,
+            crc: DEFAULT_CRC,
+            mode: DEFAULT_MODE,
+            anc_did: DEFAULT_ANC_DID as u8,
+            anc_sdid: DEFAULT_ANC_SDID as u8,
+        }
+    }
+}
+
+/// Where frames go in a video frame of `info`. Levels are written by `levels` and
+/// v210 by `lines::write_v210`, their layouts only hold the frame data.
+fn frame_layout(settings: &Settings, info: &gst_video::VideoInfo) -> FrameLayout {
+    let v210 = info.format() == gst_video::VideoFormat::V210;
+    match settings.carriage {
+        Carriage::Picture if settings.line_margin == 0 && !v210 => FrameLayout::new(info.size()),
+        Carriage::Picture => crate::lines::layout(info, settings.line_margin),
+        Carriage::Levels => FrameLayout::new(levels::capacity(crate::levels::pixels(info))),
+    }
+}
+
+/// Bytes of frame data a video frame of `info` holds
+fn frame_size(settings: &Settings, info: &gst_video::VideoInfo) -> usize {
+    frame_layout(settings, info).capacity()
+}
+
+/// Frame a scheduled message has to be sent in
+#[derive(Debug, Clone, Copy)]
+enum Schedule {
+    RunningTime(ClockTime),
+    Pts(ClockTime),
+    Timecode(Timecode),
+}
+
+/// Position of the frame being created
+struct FramePosition {
+    pts: ClockTime,
+    running_time: Option<ClockTime>,
+    duration: ClockTime,
+    timecode: Option<Timecode>,
+}
+
+impl Schedule {
+    /// Whether the scheduled position is before, within or after `frame`,
+    /// `None` if the frame has no position of that kind
+    fn cmp_frame(&self, frame: &FramePosition) -> Option<Ordering> {
+        let within = |start: ClockTime, at: ClockTime| {
+            if at < start {
+                Ordering::Less
+            } else if at < start + frame.duration {
+                Ordering::Equal
+            } else {
+                Ordering::Greater
+            }
+        };
+
+        match *self {
+            Schedule::RunningTime(at) => frame.running_time.map(|start| within(start, at)),
+            Schedule::Pts(at) => Some(within(frame.pts, at)),
+            Schedule::Timecode(at) => frame.timecode.map(|timecode| at.cmp(&timecode)),
+        }
+    }
+}
+
+/// Buffer flags of interlaced frames of `info`, with the top field first as in
+/// every interlaced mode but NTSC unless the caps say otherwise
+fn interlace_flags(info: &gst_video::VideoInfo) -> gst_video::VideoBufferFlags {
+    if info.interlace_mode() != gst_video::VideoInterlaceMode::Interleaved {
+        return gst_video::VideoBufferFlags::empty();
+    }
+
+    let top_field_first = match info.field_order() {
+        gst_video::VideoFieldOrder::TopFieldFirst => true,
+        gst_video::VideoFieldOrder::BottomFieldFirst => false,
+        _ => info.height() != 486 && info.height() != 480,
+    };
+    if top_field_first {
+        gst_video::VideoBufferFlags::INTERLACED | gst_video::VideoBufferFlags::TFF
+    } else {
+        gst_video::VideoBufferFlags::INTERLACED
+    }
+}
+
+/// PTS of the `frame_num`th frame, exact for fractional frame rates such as 30000/1001
+fn frame_pts(info: &gst_video::VideoInfo, frame_num: u64) -> ClockTime {
+    let fps = info.fps();
+    // Scaled in 128 bits as gst_util_uint64_scale does
+    let nseconds = frame_num as u128 * ClockTime::SECOND.nseconds() as u128 * fps.denom() as u128
+        / fps.numer() as u128;
+    ClockTime::from_nseconds(nseconds as u64)
+}
+
+/// Timecode of the `frame_num`th frame after `start`
+fn frame_time_code(
+    info: &gst_video::VideoInfo,
+    start: Timecode,
+    frame_num: u64,
+) -> Option<gst_video::ValidVideoTimeCode> {
+    let mut timecode = to_video_time_code(start, info.fps())?;
+    timecode.add_frames(frame_num as i64);
+
+    Some(timecode)
+}
+
+struct QueuedMessage {
+    channel: u8,
+    flags: u8,
+    data: Vec<u8>,
+    // Set by `MessageEncoder::compress`, so messages that wait a few frames for
+    // room are only compressed once. `Some(None)` if it didn't make them smaller.
+    compressed: Option<Option<Vec<u8>>>,
+}
+
+impl QueuedMessage {
+    fn new(channel: u8, data: Vec<u8>) -> Self {
+        QueuedMessage::with_type(channel, RecordType::Data, data)
+    }
+
+    fn with_type(channel: u8, record_type: RecordType, data: Vec<u8>) -> Self {
+        QueuedMessage {
+            channel,
+            flags: record_type.flags(),
+            data,
+            compressed: None,
+        }
+    }
+
+    fn record(&self) -> Record<'_> {
+        Record {
+            channel: self.channel,
+            flags: self.flags,
+            data: &self.data,
+        }
+    }
+
+    /// Record as it goes out before it is encrypted or signed, compressed if that
+    /// made it smaller
+    fn unprotected_record(&self) -> Record<'_> {
+        match self.compressed {
+            Some(Some(ref compressed)) => Record {
+                channel: self.channel,
+                flags: self.flags | RECORD_FLAG_COMPRESSED,
+                data: compressed,
+            },
+            _ => self.record(),
+        }
+    }
+
+    /// The message of `unprotected_record`
+    fn into_unprotected(self) -> QueuedMessage {
+        match self.compressed {
+            Some(Some(compressed)) => QueuedMessage {
+                channel: self.channel,
+                flags: self.flags | RECORD_FLAG_COMPRESSED,
+                data: compressed,
+                compressed: Some(None),
+            },
+            _ => self,
+        }
+    }
+}
+
+/// How messages are compressed and encrypted or signed before they go in a frame,
+/// set up on start
+#[derive(Clone)]
+struct MessageEncoder {
+    compression: Option<Compression>,
+    dictionary: Arc<Vec<u8>>,
+    keys: Keys,
+    key_id: u8,
+    key_mode: KeyMode,
+    // Signing timestamps keep increasing even if the clock goes back
+    last_timestamp: u64,
+}
+
+impl Default for MessageEncoder {
+    fn default() -> Self {
+        MessageEncoder {
+            compression: None,
+            dictionary: Arc::new(Vec::new()),
+            keys: Keys::default(),
+            key_id: DEFAULT_KEY_ID as u8,
+            key_mode: DEFAULT_KEY_MODE,
+            last_timestamp: 0,
+        }
+    }
+}
+
+impl MessageEncoder {
+    /// Bytes added to every message, compression is skipped if it doesn't help
+    fn overhead(&self) -> usize {
+        if self.keys.is_empty() {
+            return 0;
+        }
+
+        match self.key_mode {
+            KeyMode::Encrypt => ENCRYPTION_OVERHEAD,
+            KeyMode::Sign => SIGNING_OVERHEAD,
+        }
+    }
+
+    /// Compresses `message` unless that was done before. Records forwarded from
+    /// ST 2038 can be compressed or protected already.
+    fn compress(&self, message: &mut QueuedMessage) {
+        if message.compressed.is_some() {
+            return;
+        }
+
+        // Compressed data is encrypted or signed, not the other way round
+        let flags = RECORD_FLAG_COMPRESSED | RECORD_FLAG_ENCRYPTED | RECORD_FLAG_SIGNED;
+        message.compressed = Some(match self.compression {
+            Some(compression) if message.flags & flags == 0 => {
+                compress(compression, &self.dictionary, &message.data)
+            }
+            _ => None,
+        });
+    }
+
+    /// Whether `protect` encrypts or signs records with `flags`
+    fn protects(&self, flags: u8) -> bool {
+        !self.keys.is_empty() && flags & (RECORD_FLAG_ENCRYPTED | RECORD_FLAG_SIGNED) == 0
+    }
+
+    /// Room `message` takes up in a frame coded with `coding`, compressing it
+    /// first. Ciphertext and signatures are random, so messages that get protected
+    /// are measured by the worst case.
+    fn measure(&self, coding: PayloadCoding, message: &mut QueuedMessage) -> usize {
+        self.compress(message);
+
+        let record = message.unprotected_record();
+        if self.protects(record.flags) {
+            coding.max_record_len(record.data.len() + self.overhead())
+        } else {
+            coding.record_len(&record)
+        }
+    }
+
+    /// `message` as it is put in the frame with header `frame_header`, after
+    /// `measure`. Only called for messages that are sent, every call encrypts or
+    /// signs anew.
+    fn encode(&mut self, frame_header: &[u8], message: QueuedMessage) -> QueuedMessage {
+        let message = message.into_unprotected();
+        self.protect(frame_header, &message).unwrap_or(message)
+    }
+
+    /// Encrypted or signed copy of `message` for the frame with header
+    /// `frame_header`, `None` if there are no keys or it is encrypted or signed
+    /// already
+    fn protect(&mut self, frame_header: &[u8], message: &QueuedMessage) -> Option<QueuedMessage> {
+        if !self.protects(message.flags) {
+            return None;
+        }
+
+        let (flags, data) = match self.key_mode {
+            KeyMode::Encrypt => {
+                let flags = message.flags | RECORD_FLAG_ENCRYPTED;
+                let aad = Record::aad(frame_header, message.channel, flags);
+                (flags, self.keys.encrypt(self.key_id, &aad, &message.data))
+            }
+            KeyMode::Sign => {
+                let flags = message.flags | RECORD_FLAG_SIGNED;
+                let aad = Record::aad(frame_header, message.channel, flags);
+                self.last_timestamp = unix_time_us().max(self.last_timestamp + 1);
+                let data = self
+                    .keys
+                    .sign(self.key_id, &aad, self.last_timestamp, &message.data);
+                (flags, data)
+            }
+        };
+
+        Some(QueuedMessage {
+            channel: message.channel,
+            flags,
+            data: data.expect("key id checked against the keys"),
+            compressed: Some(None),
+        })
+    }
+}
+
+struct MessageQueue {
+    messages: VecDeque<QueuedMessage>,
+    bytes: u64,
+    // Held back until their frame, counted against the limits like the others
+    scheduled: Vec<(Schedule, QueuedMessage)>,
+    // Set while stopped, so blocked `send-data` callers are released
+    flushing: bool,
+    need_data_signalled: bool,
+    enough_data_signalled: bool,
+    // Thread `create` runs on, where signal handlers must not block on the queue
+    streaming_thread: Option<thread::ThreadId>,
+}
+
+impl Default for MessageQueue {
+    fn default() -> Self {
+        MessageQueue {
+            messages: VecDeque::new(),
+            bytes: 0,
+            scheduled: Vec::new(),
+            // Stopped until `start`
+            flushing: true,
+            need_data_signalled: false,
+            enough_data_signalled: false,
+            streaming_thread: None,
+        }
+    }
+}
+
+impl MessageQueue {
+    /// Messages waiting to be sent, scheduled or not
+    fn len(&self) -> usize {
+        self.messages.len() + self.scheduled.len()
+    }
+
+    fn is_full(&self, settings: &Settings) -> bool {
+        (settings.max_queued_messages > 0 && self.len() >= settings.max_queued_messages as usize)
+            || (settings.max_queued_bytes > 0 && self.bytes >= settings.max_queued_bytes)
+    }
+
+    fn push_back(&mut self, message: QueuedMessage) {
+        self.bytes += message.data.len() as u64;
+        self.messages.push_back(message);
+    }
+
+    fn pop_front(&mut self) -> Option<QueuedMessage> {
+        let message = self.messages.pop_front()?;
+        self.bytes -= message.data.len() as u64;
+        Some(message)
+    }
+
+    fn push_scheduled(&mut self, schedule: Schedule, message: QueuedMessage) {
+        self.bytes += message.data.len() as u64;
+        self.scheduled.push((schedule, message));
+    }
+
+    fn remove_scheduled(&mut self, i: usize) -> (Schedule, QueuedMessage) {
+        let (schedule, message) = self.scheduled.remove(i);
+        self.bytes -= message.data.len() as u64;
+        (schedule, message)
+    }
+
+    /// Drops the oldest message that isn't scheduled, or the one scheduled first if
+    /// there are only scheduled ones. Returns whether there was one.
+    fn drop_oldest(&mut self) -> bool {
+        if self.pop_front().is_some() {
+            return true;
+        }
+        if self.scheduled.is_empty() {
+            return false;
+        }
+        self.remove_scheduled(0);
+        true
+    }
+
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.bytes = 0;
+        self.scheduled.clear();
+    }
+
+    /// Drops what messages were compressed to, for when the compression settings
+    /// changed
+    fn forget_compressed(&mut self) {
+        let scheduled = self.scheduled.iter_mut().map(|(_, message)| message);
+        for message in self.messages.iter_mut().chain(scheduled) {
+            message.compressed = None;
+        }
+    }
+
+    /// Bytes that can still be queued before hitting `max-queued-bytes`
+    fn free_bytes(&self, settings: &Settings) -> u32 {
+        if settings.max_queued_bytes == 0 {
+            return u32::MAX;
+        }
+        settings
+            .max_queued_bytes
+            .saturating_sub(self.bytes)
+            .min(u32::MAX as u64) as u32
+    }
+}
+
 struct State {
     info: Option<gst_video::VideoInfo>,
     current_frame_num: u64,
+    encoder: MessageEncoder,
 }
 
 impl Default for State {
@@ -37,32 +771,498 @@ impl Default for State {
         State {
             info: None,
             current_frame_num: 0,
+            encoder: MessageEncoder::default(),
         }
     }
 }
 
+#[derive(Default)]
 pub struct DataSrc {
+    settings: Mutex<Settings>,
     state: Mutex<State>,
-    message_queue: Mutex<VecDeque<String>>,
+    message_queue: Mutex<MessageQueue>,
+    queue_changed: Condvar,
+    caption_pad: Mutex<Option<gst::Pad>>,
+    st2038_pad: Mutex<Option<gst::Pad>>,
+}
+
+/// Running time of `buffer` in the segment of `pad`
+fn running_time(pad: &gst::Pad, buffer: &gst::Buffer) -> Option<ClockTime> {
+    let segment = pad.sticky_event(gst::EventType::Segment, 0);
+    segment
+        .zip(buffer.pts())
+        .and_then(|(event, pts)| match event.view() {
+            gst::EventView::Segment(segment) => segment
+                .segment()
+                .downcast_ref::<ClockTime>()
+                .and_then(|segment| segment.to_running_time(pts)),
+            _ => None,
+        })
 }
 
 impl DataSrc {
-    fn push_message(&self, message: String) {
-        self.message_queue.lock().unwrap().push_back(message);
+    /// Largest message guaranteed to fit in a frame of the negotiated caps,
+    /// if there are any yet
+    fn max_payload_size(&self) -> Option<usize> {
+        let settings = self.settings.lock().unwrap().clone();
+        let coding = settings.payload_coding.coding();
+        let state = self.state.lock().unwrap();
+        let limit = coding.max_payload_size(frame_size(&settings, state.info.as_ref()?));
+        Some(limit.saturating_sub(state.encoder.overhead()))
+    }
+
+    /// Whether `message` fits in a frame of the negotiated caps, if there are any yet
+    fn check_fits(&self, element: &super::DataSrc, message: &QueuedMessage) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let coding = settings.payload_coding.coding();
+        let (frame_size, overhead) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.info.as_ref().map(|info| frame_size(&settings, info)),
+                state.encoder.overhead(),
+            )
+        };
+
+        // Ciphertext and signatures are random, so only the worst case can be relied on
+        let fits = |frame_size| {
+            if overhead > 0 {
+                message.data.len() + overhead <= coding.max_payload_size(frame_size)
+            } else {
+                coding.payload_fits(&message.record(), frame_size)
+            }
+        };
+
+        match frame_size {
+            Some(frame_size) if !fits(frame_size) => {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Rejecting message of {} bytes, it does not fit in a frame of {} bytes",
+                    message.data.len(),
+                    frame_size
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Queues the captions of `buffer` for the frame at its running time, or the next
+    /// one without
+    fn caption_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &super::DataSrc,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let caps = pad.current_caps().ok_or(gst::FlowError::NotNegotiated)?;
+        let is_video = caps
+            .structure(0)
+            .map_or(false, |structure| structure.name() == "video/x-raw");
+
+        let captions: Vec<Vec<u8>> = if is_video {
+            buffer
+                .iter_meta::<gst_video::VideoCaptionMeta>()
+                .filter_map(|meta| {
+                    let caption_type = from_video_caption_type(meta.caption_type())?;
+                    Some(pack_captions(caption_type, meta.data()))
+                })
+                .collect()
+        } else {
+            let caption_type =
+                from_video_caption_type(gst_video::VideoCaptionType::from_caps(&caps))
+                    .ok_or(gst::FlowError::NotNegotiated)?;
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            vec![pack_captions(caption_type, &map)]
+        };
+
+        let running_time = running_time(pad, &buffer);
+        if let Some((running_time, next)) = running_time.zip(self.next_frame_running_time(element))
+        {
+            // Captions are no use once their frame is gone
+            if running_time < next {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Dropping captions at {}, the next frame is at {}",
+                    running_time,
+                    next
+                );
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+
+        for data in captions {
+            let message = QueuedMessage::with_type(DEFAULT_CHANNEL, RecordType::Captions, data);
+            match running_time {
+                Some(running_time) => self.push_scheduled_message(
+                    element,
+                    Schedule::RunningTime(running_time),
+                    message,
+                ),
+                None => self.push_message(element, message),
+            };
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Running time of the next frame created, `None` before caps are set
+    fn next_frame_running_time(&self, element: &super::DataSrc) -> Option<ClockTime> {
+        let pts = {
+            let state = self.state.lock().unwrap();
+            frame_pts(state.info.as_ref()?, state.current_frame_num)
+        };
+
+        element
+            .segment()
+            .downcast_ref::<ClockTime>()
+            .and_then(|segment| segment.to_running_time(pts))
+    }
+
+    /// Queues the records of the frame carried in the ST 2038 packets of `buffer` for
+    /// the frame at its running time, or the next one without. Compressed records go
+    /// out as they are, encrypted and signed ones are dropped.
+    fn st2038_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &super::DataSrc,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let ids = {
+            let settings = self.settings.lock().unwrap();
+            (settings.anc_did, settings.anc_sdid)
+        };
+
+        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+        let packets = match st2038::parse_st2038_packets(&map) {
+            Ok(packets) => packets,
+            Err(err) => {
+                gst_warning!(CAT, obj: element, "{}", err);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+        let data: Vec<u8> = packets
+            .into_iter()
+            .filter(|packet| (packet.did, packet.sdid) == ids)
+            .flat_map(|packet| packet.data)
+            .collect();
+
+        let decoded = match FrameDecoder::new(FrameLayout::new(data.len())).decode(&data) {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => return Ok(gst::FlowSuccess::Ok),
+            Err(err) => {
+                gst_warning!(CAT, obj: element, "{}", err);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        let running_time = running_time(pad, &buffer);
+        for record in decoded.records() {
+            // Bound to the header of the frame they came in
+            if record.flags & (RECORD_FLAG_ENCRYPTED | RECORD_FLAG_SIGNED) != 0 {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Dropping encrypted or signed record on channel {}, it can't be moved \
+                     to another frame",
+                    record.channel
+                );
+                continue;
+            }
+
+            let message = QueuedMessage {
+                channel: record.channel,
+                flags: record.flags,
+                data: record.data.to_vec(),
+                compressed: None,
+            };
+            match running_time {
+                Some(running_time) => self.push_scheduled_message(
+                    element,
+                    Schedule::RunningTime(running_time),
+                    message,
+                ),
+                None => self.push_message(element, message),
+            };
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Queues a SCTE-35 splice info section like `push_message`
+    fn push_scte35(&self, element: &super::DataSrc, section: Vec<u8>) -> bool {
+        if let Err(err) = scte35::check_splice_info_section(&section) {
+            gst_warning!(CAT, obj: element, "Not sending SCTE-35: {}", err);
+            return false;
+        }
+
+        self.push_message(
+            element,
+            QueuedMessage::with_type(DEFAULT_CHANNEL, RecordType::Scte35, section),
+        )
     }
-}
 
-impl Default for DataSrc {
-    fn default() -> DataSrc {
-        let message_queue = Mutex::new(VecDeque::with_capacity(5));
-        DataSrc {
-            state: Default::default(),
-            message_queue,
+    /// Queues `message` for sending, returns `false` if it was rejected or dropped
+    fn push_message(&self, element: &super::DataSrc, message: QueuedMessage) -> bool {
+        self.enqueue(element, None, message)
+    }
+
+    /// Holds `message` back until the frame at `schedule`, like `push_message`
+    /// Whether frames carry a timecode, `true` until caps are negotiated
+    fn frames_have_timecodes(&self) -> bool {
+        let timecode_start = self.settings.lock().unwrap().timecode_start;
+        match self.state.lock().unwrap().info {
+            Some(ref info) => frame_time_code(info, timecode_start, 0).is_some(),
+            None => true,
         }
     }
+
+    fn push_scheduled_message(
+        &self,
+        element: &super::DataSrc,
+        schedule: Schedule,
+        message: QueuedMessage,
+    ) -> bool {
+        gst_debug!(CAT, obj: element, "Scheduling message at {:?}", schedule);
+
+        self.enqueue(element, Some(schedule), message)
+    }
+
+    /// Queues `message` for the next frame or the frame at `schedule`, as the limits
+    /// and leaky type allow
+    fn enqueue(
+        &self,
+        element: &super::DataSrc,
+        schedule: Option<Schedule>,
+        message: QueuedMessage,
+    ) -> bool {
+        if !self.check_fits(element, &message) {
+            return false;
+        }
+
+        let settings = self.settings.lock().unwrap().clone();
+        let mut queue = self.message_queue.lock().unwrap();
+
+        while queue.is_full(&settings) {
+            match settings.leaky_type {
+                LeakyType::None => break,
+                LeakyType::DropNewest => {
+                    gst_warning!(CAT, obj: element, "Queue is full, dropping newest message");
+                    drop(queue);
+                    self.signal_enough_data(element);
+                    return false;
+                }
+                LeakyType::DropOldest => {
+                    gst_warning!(CAT, obj: element, "Queue is full, dropping oldest message");
+                    if !queue.drop_oldest() {
+                        break;
+                    }
+                }
+                LeakyType::Block => {
+                    if queue.flushing {
+                        gst_debug!(CAT, obj: element, "Flushing, dropping message");
+                        return false;
+                    }
+                    // Only the streaming thread makes room, so `need-data` and other
+                    // handlers on it would wait forever
+                    if queue.streaming_thread == Some(thread::current().id()) {
+                        gst_debug!(CAT, obj: element, "Queue is full, not blocking the streaming thread");
+                        break;
+                    }
+                    gst_debug!(CAT, obj: element, "Queue is full, waiting for room");
+                    queue = self.queue_changed.wait(queue).unwrap();
+                }
+            }
+        }
+
+        match schedule {
+            Some(schedule) => queue.push_scheduled(schedule, message),
+            None => queue.push_back(message),
+        }
+        queue.need_data_signalled = false;
+        let full = queue.is_full(&settings);
+        drop(queue);
+
+        if full {
+            self.signal_enough_data(element);
+        }
+
+        true
+    }
+
+    /// Takes the scheduled messages due in `frame`, then as many queued messages
+    /// as fit in the room left in `frame_encoder`, as encoded by `encoder`
+    fn pop_messages(
+        &self,
+        element: &super::DataSrc,
+        frame_encoder: &FrameEncoder,
+        frame: &FramePosition,
+        encoder: &mut MessageEncoder,
+    ) -> Vec<QueuedMessage> {
+        let capacity = frame_encoder.remaining();
+        let coding = frame_encoder.coding();
+        let settings = self.settings.lock().unwrap().clone();
+        let mut queue = self.message_queue.lock().unwrap();
+        queue.streaming_thread = Some(thread::current().id());
+
+        let mut messages = Vec::new();
+        let mut dropped = Vec::new();
+        let mut unschedulable = Vec::new();
+        let mut late = Vec::new();
+        let mut used = 0;
+
+        let mut i = 0;
+        while i < queue.scheduled.len() {
+            let (schedule, ref mut message) = queue.scheduled[i];
+            let ordering = match schedule.cmp_frame(frame) {
+                Some(Ordering::Greater) => {
+                    i += 1;
+                    continue;
+                }
+                None => {
+                    // Frames have no position of that kind, it would never leave the queue
+                    let (schedule, message) = queue.remove_scheduled(i);
+                    unschedulable.push((schedule, message.data.len()));
+                    continue;
+                }
+                Some(ordering) => ordering,
+            };
+
+            let length = encoder.measure(coding, message);
+            if used + length > capacity && length <= capacity {
+                // Stays for the next frame, where it will be late
+                i += 1;
+                continue;
+            }
+
+            let (_, message) = queue.remove_scheduled(i);
+            if length > capacity {
+                dropped.push(message.data.len());
+                continue;
+            }
+            if ordering == Ordering::Less
+                && message.record().record_type() == Some(RecordType::Captions)
+            {
+                // Captions of a frame that is gone would only come out of sync
+                gst_debug!(CAT, obj: element, "Dropping late captions");
+                continue;
+            }
+            if ordering == Ordering::Less {
+                late.push((message.channel as u32, message.data.clone()));
+            }
+            let message = encoder.encode(frame_encoder.header(), message);
+            used += coding.record_len(&message.record());
+            messages.push(message);
+        }
+
+        while let Some(message) = queue.messages.front_mut() {
+            let length = encoder.measure(coding, message);
+            if length > capacity {
+                // Caps were renegotiated to something smaller after it got queued
+                dropped.push(queue.pop_front().unwrap().data.len());
+                continue;
+            }
+            if used + length > capacity {
+                break;
+            }
+            let message = queue.pop_front().unwrap();
+            let message = encoder.encode(frame_encoder.header(), message);
+            used += coding.record_len(&message.record());
+            messages.push(message);
+        }
+        self.queue_changed.notify_all();
+
+        if !queue.is_full(&settings) {
+            queue.enough_data_signalled = false;
+        }
+
+        let need_data = if queue.messages.is_empty() && !queue.need_data_signalled {
+            queue.need_data_signalled = true;
+            Some(queue.free_bytes(&settings))
+        } else {
+            None
+        };
+        drop(queue);
+
+        for length in dropped {
+            gst::element_warning!(
+                element,
+                gst::StreamError::Encode,
+                [
+                    "Dropping message of {} bytes, it does not fit in a frame",
+                    length
+                ]
+            );
+        }
+
+        for (schedule, length) in unschedulable {
+            gst::element_warning!(
+                element,
+                gst::StreamError::Encode,
+                [
+                    "Dropping message of {} bytes, no frame is at {:?}",
+                    length,
+                    schedule
+                ]
+            );
+        }
+
+        for (channel, data) in late {
+            gst_warning!(CAT, obj: element, "Sending late message on channel {}", channel);
+            element
+                .emit_by_name(
+                    SIGNAL_DATA_LATE,
+                    &[&channel, &glib::Bytes::from_owned(data)],
+                )
+                .unwrap();
+        }
+
+        if let Some(length) = need_data {
+            element.emit_by_name(SIGNAL_NEED_DATA, &[&length]).unwrap();
+        }
+
+        messages
+    }
+
+    fn signal_enough_data(&self, element: &super::DataSrc) {
+        let mut queue = self.message_queue.lock().unwrap();
+        if queue.enough_data_signalled {
+            return;
+        }
+        queue.enough_data_signalled = true;
+        drop(queue);
+
+        element.emit_by_name(SIGNAL_ENOUGH_DATA, &[]).unwrap();
+    }
+
+    fn flush(&self, element: &super::DataSrc) {
+        let mut queue = self.message_queue.lock().unwrap();
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Flushing {} queued messages",
+            queue.len()
+        );
+        queue.clear();
+        queue.enough_data_signalled = false;
+        self.queue_changed.notify_all();
+    }
+
+    fn set_flushing(&self, flushing: bool) {
+        let mut queue = self.message_queue.lock().unwrap();
+        queue.flushing = flushing;
+        self.queue_changed.notify_all();
+    }
 }
 
-impl DataSrc {}
+fn channel_arg(element: &super::DataSrc, channel: u32) -> Option<u8> {
+    let parsed = u8::try_from(channel).ok();
+    if parsed.is_none() {
+        gst_warning!(CAT, obj: element, "Invalid channel {}", channel);
+    }
+    parsed
+}
 
 #[glib::object_subclass]
 impl ObjectSubclass for DataSrc {
@@ -83,24 +1283,716 @@ impl ObjectImpl for DataSrc {
         obj.set_format(gst::Format::Time);
     }
 
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpec::new_uint(
+                    "max-queued-messages",
+                    "Max Queued Messages",
+                    "Maximum number of messages waiting to be sent (0 = unlimited)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_QUEUED_MESSAGES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_uint64(
+                    "max-queued-bytes",
+                    "Max Queued Bytes",
+                    "Maximum number of message bytes waiting to be sent (0 = unlimited)",
+                    0,
+                    u64::MAX,
+                    DEFAULT_MAX_QUEUED_BYTES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_enum(
+                    "leaky-type",
+                    "Leaky Type",
+                    "What to do with new messages when the queue is full",
+                    LeakyType::static_type(),
+                    DEFAULT_LEAKY_TYPE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_enum(
+                    "schedule-format",
+                    "Schedule Format",
+                    "How the time passed to send-data-at is matched against frames",
+                    ScheduleFormat::static_type(),
+                    DEFAULT_SCHEDULE_FORMAT as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_string(
+                    "timecode-start",
+                    "Timecode Start",
+                    "Timecode of the first frame as hh:mm:ss:ff, carried with the data",
+                    Some("00:00:00:00"),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_string(
+                    "key",
+                    "Key",
+                    "Keys to encrypt or sign messages with as [<id>:]<64 hex digits>, \
+                     separated by commas (empty = send messages as they are)",
+                    None,
+                    glib::ParamFlags::WRITABLE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_string(
+                    "key-file",
+                    "Key File",
+                    "File with more keys in the same format as key",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "key-id",
+                    "Key Id",
+                    "Id of the key messages are encrypted or signed with, change it to rotate keys",
+                    0,
+                    u8::MAX as u32,
+                    DEFAULT_KEY_ID,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpec::new_enum(
+                    "key-mode",
+                    "Key Mode",
+                    "Whether messages are encrypted or only signed with the key",
+                    KeyMode::static_type(),
+                    DEFAULT_KEY_MODE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_enum(
+                    "compression",
+                    "Compression",
+                    "How messages are compressed, those that don't get smaller are sent as they are",
+                    CompressionType::static_type(),
+                    DEFAULT_COMPRESSION as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_string(
+                    "compression-dictionary",
+                    "Compression Dictionary",
+                    "File with a dictionary shared with datasink, for small repetitive messages \
+                     (zstd and lz4 only)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_enum(
+                    "payload-coding",
+                    "Payload Coding",
+                    "How 0 and 255 are kept out of frames",
+                    PayloadCodingType::static_type(),
+                    DEFAULT_PAYLOAD_CODING as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_enum(
+                    "carriage",
+                    "Carriage",
+                    "Where frames go in the video",
+                    Carriage::static_type(),
+                    DEFAULT_CARRIAGE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "line-margin",
+                    "Line Margin",
+                    "Pixels at the right end of every line to leave out of frames in the \
+                     picture, so they survive being shifted right by as many (0 = fill the \
+                     whole picture)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_LINE_MARGIN,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_boolean(
+                    "crc",
+                    "CRC",
+                    "End frames in a CRC of their payload",
+                    DEFAULT_CRC,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_enum(
+                    "mode",
+                    "Mode",
+                    "Video mode to pick where downstream allows more than one",
+                    Mode::static_type(),
+                    DEFAULT_MODE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "anc-did",
+                    "ANC DID",
+                    "Data identifier of the ST 2038 packets frames are taken from",
+                    0,
+                    u8::MAX as u32,
+                    DEFAULT_ANC_DID,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "anc-sdid",
+                    "ANC SDID",
+                    "Secondary data identifier of the ST 2038 packets frames are taken from",
+                    0,
+                    u8::MAX as u32,
+                    DEFAULT_ANC_SDID,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpec::new_uint(
+                    "max-payload-size",
+                    "Max Payload Size",
+                    "Largest message in bytes guaranteed to fit in a frame of the negotiated caps \
+                     (0 until caps are negotiated)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpec::new_uint(
+                    "queued",
+                    "Queued",
+                    "Number of messages waiting to be sent",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "max-queued-messages" => {
+                let max_queued_messages = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing max-queued-messages from {} to {}",
+                    settings.max_queued_messages,
+                    max_queued_messages
+                );
+                settings.max_queued_messages = max_queued_messages;
+            }
+            "max-queued-bytes" => {
+                let max_queued_bytes = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing max-queued-bytes from {} to {}",
+                    settings.max_queued_bytes,
+                    max_queued_bytes
+                );
+                settings.max_queued_bytes = max_queued_bytes;
+            }
+            "leaky-type" => {
+                let leaky_type = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing leaky-type from {:?} to {:?}",
+                    settings.leaky_type,
+                    leaky_type
+                );
+                settings.leaky_type = leaky_type;
+            }
+            "schedule-format" => {
+                let schedule_format = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing schedule-format from {:?} to {:?}",
+                    settings.schedule_format,
+                    schedule_format
+                );
+                settings.schedule_format = schedule_format;
+            }
+            "timecode-start" => {
+                let timecode_start = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_default();
+                match timecode_start.parse() {
+                    Ok(timecode_start) => {
+                        gst_info!(
+                            CAT,
+                            obj: obj,
+                            "Changing timecode-start from {} to {}",
+                            settings.timecode_start,
+                            timecode_start
+                        );
+                        settings.timecode_start = timecode_start;
+                    }
+                    Err(err) => gst_error!(CAT, obj: obj, "{}", err),
+                }
+            }
+            "key" => {
+                gst_info!(CAT, obj: obj, "Changing key");
+                settings.key = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_default();
+            }
+            "key-file" => {
+                let key_file = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing key-file from {:?} to {:?}",
+                    settings.key_file,
+                    key_file
+                );
+                settings.key_file = key_file;
+            }
+            "key-id" => {
+                let key_id = value.get::<u32>().expect("type checked upstream") as u8;
+                let mut state = self.state.lock().unwrap();
+                let keys = &state.encoder.keys;
+                if !keys.is_empty() && !keys.contains(key_id) {
+                    gst_error!(CAT, obj: obj, "No key with id {}", key_id);
+                    return;
+                }
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing key-id from {} to {}",
+                    settings.key_id,
+                    key_id
+                );
+                settings.key_id = key_id;
+                state.encoder.key_id = key_id;
+            }
+            "key-mode" => {
+                let key_mode = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing key-mode from {:?} to {:?}",
+                    settings.key_mode,
+                    key_mode
+                );
+                settings.key_mode = key_mode;
+            }
+            "compression" => {
+                let compression = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing compression from {:?} to {:?}",
+                    settings.compression,
+                    compression
+                );
+                settings.compression = compression;
+            }
+            "compression-dictionary" => {
+                let compression_dictionary = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing compression-dictionary from {:?} to {:?}",
+                    settings.compression_dictionary,
+                    compression_dictionary
+                );
+                settings.compression_dictionary = compression_dictionary;
+            }
+            "payload-coding" => {
+                let payload_coding = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing payload-coding from {:?} to {:?}",
+                    settings.payload_coding,
+                    payload_coding
+                );
+                settings.payload_coding = payload_coding;
+            }
+            "carriage" => {
+                let carriage = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing carriage from {:?} to {:?}",
+                    settings.carriage,
+                    carriage
+                );
+                settings.carriage = carriage;
+            }
+            "line-margin" => {
+                let line_margin = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing line-margin from {} to {}",
+                    settings.line_margin,
+                    line_margin
+                );
+                settings.line_margin = line_margin;
+            }
+            "crc" => {
+                let crc = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing crc from {} to {}",
+                    settings.crc,
+                    crc
+                );
+                settings.crc = crc;
+            }
+            "mode" => {
+                let mode = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing mode from {:?} to {:?}",
+                    settings.mode,
+                    mode
+                );
+                settings.mode = mode;
+            }
+            "anc-did" => {
+                let anc_did = value.get::<u32>().expect("type checked upstream") as u8;
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing anc-did from {:#04x} to {:#04x}",
+                    settings.anc_did,
+                    anc_did
+                );
+                settings.anc_did = anc_did;
+            }
+            "anc-sdid" => {
+                let anc_sdid = value.get::<u32>().expect("type checked upstream") as u8;
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing anc-sdid from {:#04x} to {:#04x}",
+                    settings.anc_sdid,
+                    anc_sdid
+                );
+                settings.anc_sdid = anc_sdid;
+            }
+            _ => unimplemented!(),
+        }
+        drop(settings);
+
+        // Limits may have grown, wake up blocked senders
+        self.queue_changed.notify_all();
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "max-queued-messages" => self.settings.lock().unwrap().max_queued_messages.to_value(),
+            "max-queued-bytes" => self.settings.lock().unwrap().max_queued_bytes.to_value(),
+            "leaky-type" => self.settings.lock().unwrap().leaky_type.to_value(),
+            "schedule-format" => self.settings.lock().unwrap().schedule_format.to_value(),
+            "timecode-start" => self
+                .settings
+                .lock()
+                .unwrap()
+                .timecode_start
+                .to_string()
+                .to_value(),
+            "key-file" => self.settings.lock().unwrap().key_file.to_value(),
+            "key-id" => (self.settings.lock().unwrap().key_id as u32).to_value(),
+            "key-mode" => self.settings.lock().unwrap().key_mode.to_value(),
+            "compression" => self.settings.lock().unwrap().compression.to_value(),
+            "compression-dictionary" => self
+                .settings
+                .lock()
+                .unwrap()
+                .compression_dictionary
+                .to_value(),
+            "payload-coding" => self.settings.lock().unwrap().payload_coding.to_value(),
+            "carriage" => self.settings.lock().unwrap().carriage.to_value(),
+            "line-margin" => self.settings.lock().unwrap().line_margin.to_value(),
+            "crc" => self.settings.lock().unwrap().crc.to_value(),
+            "mode" => self.settings.lock().unwrap().mode.to_value(),
+            "anc-did" => (self.settings.lock().unwrap().anc_did as u32).to_value(),
+            "anc-sdid" => (self.settings.lock().unwrap().anc_sdid as u32).to_value(),
+            "max-payload-size" => (self.max_payload_size().unwrap_or(0) as u32).to_value(),
+            "queued" => (self.message_queue.lock().unwrap().len() as u32).to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
     fn signals() -> &'static [glib::subclass::Signal] {
         static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
-            vec![glib::subclass::Signal::builder(
-                "send-data",
-                &[String::static_type().into()],
-                glib::types::Type::UNIT.into(),
-            )
-            .action()
-            .class_handler(|_, args| {
-                let element = args[0].get::<super::DataSrc>().expect("signal arg");
-                let data = args[1].get::<String>().expect("signal arg");
+            vec![
+                glib::subclass::Signal::builder(
+                    "send-data",
+                    &[String::static_type().into()],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let data = args[1].get::<String>().expect("signal arg");
 
-                let datasrc = DataSrc::from_instance(&element);
-                datasrc.push_message(data);
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc.push_message(
+                        &element,
+                        QueuedMessage::new(DEFAULT_CHANNEL, data.into_bytes()),
+                    );
 
-                None
-            })
-            .build()]
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-data-on-channel",
+                    &[u32::static_type().into(), String::static_type().into()],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let data = args[2].get::<String>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc
+                        .push_message(&element, QueuedMessage::new(channel, data.into_bytes()));
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-klv",
+                    &[u32::static_type().into(), glib::Bytes::static_type().into()],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let data = args[2].get::<glib::Bytes>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    if let Err(err) = klv::parse_klv_packets(&data) {
+                        gst_warning!(CAT, obj: &element, "Not sending KLV: {}", err);
+                        return Some(false.to_value());
+                    }
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc.push_message(
+                        &element,
+                        QueuedMessage::with_type(channel, RecordType::Klv, data.to_vec()),
+                    );
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-scte35",
+                    &[glib::Bytes::static_type().into()],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let section = args[1].get::<glib::Bytes>().expect("signal arg");
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc.push_scte35(&element, section.to_vec());
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-structure",
+                    &[
+                        u32::static_type().into(),
+                        gst::Structure::static_type().into(),
+                    ],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let structure = args[2].get::<gst::Structure>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc.push_message(
+                        &element,
+                        QueuedMessage::with_type(
+                            channel,
+                            RecordType::Structure,
+                            structure.to_string().into_bytes(),
+                        ),
+                    );
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-variant",
+                    &[
+                        u32::static_type().into(),
+                        glib::Variant::static_type().into(),
+                    ],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let variant = args[2].get::<glib::Variant>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let accepted = datasrc.push_message(
+                        &element,
+                        QueuedMessage::with_type(
+                            channel,
+                            RecordType::Variant,
+                            variant_to_bytes(&variant),
+                        ),
+                    );
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-data-at",
+                    &[
+                        u32::static_type().into(),
+                        String::static_type().into(),
+                        u64::static_type().into(),
+                    ],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let data = args[2].get::<String>().expect("signal arg");
+                    let time = args[3].get::<u64>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    if time == u64::MAX {
+                        gst_warning!(CAT, obj: &element, "Can't schedule at GST_CLOCK_TIME_NONE");
+                        return Some(false.to_value());
+                    }
+                    let time = ClockTime::from_nseconds(time);
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    let schedule = match datasrc.settings.lock().unwrap().schedule_format {
+                        ScheduleFormat::RunningTime => Schedule::RunningTime(time),
+                        ScheduleFormat::Pts => Schedule::Pts(time),
+                    };
+                    let accepted = datasrc.push_scheduled_message(
+                        &element,
+                        schedule,
+                        QueuedMessage::new(channel, data.into_bytes()),
+                    );
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    "send-data-at-timecode",
+                    &[
+                        u32::static_type().into(),
+                        String::static_type().into(),
+                        String::static_type().into(),
+                    ],
+                    bool::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::DataSrc>().expect("signal arg");
+                    let channel = args[1].get::<u32>().expect("signal arg");
+                    let data = args[2].get::<String>().expect("signal arg");
+                    let timecode = args[3].get::<String>().expect("signal arg");
+
+                    let channel = match channel_arg(&element, channel) {
+                        Some(channel) => channel,
+                        None => return Some(false.to_value()),
+                    };
+
+                    let timecode = match timecode.parse::<Timecode>() {
+                        Ok(timecode) => timecode,
+                        Err(err) => {
+                            gst_warning!(CAT, obj: &element, "{}", err);
+                            return Some(false.to_value());
+                        }
+                    };
+
+                    let datasrc = DataSrc::from_instance(&element);
+                    if !datasrc.frames_have_timecodes() {
+                        gst_warning!(CAT, obj: &element, "Frames have no timecode");
+                        return Some(false.to_value());
+                    }
+
+                    let accepted = datasrc.push_scheduled_message(
+                        &element,
+                        Schedule::Timecode(timecode),
+                        QueuedMessage::new(channel, data.into_bytes()),
+                    );
+
+                    Some(accepted.to_value())
+                })
+                .build(),
+                glib::subclass::Signal::builder("flush", &[], glib::types::Type::UNIT.into())
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::DataSrc>().expect("signal arg");
+
+                        let datasrc = DataSrc::from_instance(&element);
+                        datasrc.flush(&element);
+
+                        None
+                    })
+                    .build(),
+                // Messages as they were sent, binary ones such as KLV included
+                glib::subclass::Signal::builder(
+                    SIGNAL_DATA_LATE,
+                    &[u32::static_type().into(), glib::Bytes::static_type().into()],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_NEED_DATA,
+                    &[u32::static_type().into()],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_ENOUGH_DATA,
+                    &[],
+                    glib::types::Type::UNIT.into(),
+                )
+                .build(),
+            ]
         });
 
         SIGNALS.as_ref()
@@ -123,28 +2015,15 @@ impl ElementImpl for DataSrc {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let caps = gst::Caps::new_simple(
-                "video/x-raw",
-                &[
-                    (
-                        "format",
-                        &gst::List::new(&[
-                            &gst_video::VideoFormat::Uyvy.to_str(),
-                            &gst_video::VideoFormat::Argb.to_str(),
-                            &gst_video::VideoFormat::Bgra.to_str(),
-                        ]),
-                    ),
-                    ("width", &gst::IntRange::<i32>::new(1920, i32::MAX)),
-                    ("height", &gst::IntRange::<i32>::new(1080, i32::MAX)),
-                    (
-                        "framerate",
-                        &gst::FractionRange::new(
-                            gst::Fraction::new(0, 1),
-                            gst::Fraction::new(i32::MAX, 1),
-                        ),
-                    ),
-                ],
-            );
+            let mut formats = Vec::new();
+            for carriage in &[Carriage::Picture, Carriage::Levels] {
+                for format in carriage.formats() {
+                    if !formats.contains(format) {
+                        formats.push(*format);
+                    }
+                }
+            }
+            let caps = video_caps(&formats);
 
             let src_pad_template = gst::PadTemplate::new(
                 "src",
@@ -154,17 +2033,167 @@ impl ElementImpl for DataSrc {
             )
             .unwrap();
 
-            vec![src_pad_template]
+            // Captions to embed, on their own or as `VideoCaptionMeta` of video
+            let caption_pad_template = gst::PadTemplate::new(
+                "captions",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &caption_sink_caps(),
+            )
+            .unwrap();
+
+            // ST 2038 packets with frames to carry, as `datasink` outputs them
+            let st2038_pad_template = gst::PadTemplate::new(
+                "st2038",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &gst::Caps::builder("meta/x-st-2038")
+                    .field("alignment", &"frame")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, caption_pad_template, st2038_pad_template]
         });
         PAD_TEMPLATES.as_ref()
     }
+
+    fn request_new_pad(
+        &self,
+        element: &Self::Type,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let name_template = templ.name_template();
+        let is_captions = name_template == "captions";
+        let mut sink_pad = if is_captions {
+            self.caption_pad.lock().unwrap()
+        } else {
+            self.st2038_pad.lock().unwrap()
+        };
+        if sink_pad.is_some() {
+            gst_error!(CAT, obj: element, "There is a {} pad already", name_template);
+            return None;
+        }
+
+        let builder = gst::Pad::builder_with_template(templ, Some(name_template.as_str()));
+        let builder = if is_captions {
+            builder.chain_function(|pad, parent, buffer| {
+                DataSrc::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |datasrc, element| datasrc.caption_chain(pad, element, buffer),
+                )
+            })
+        } else {
+            builder.chain_function(|pad, parent, buffer| {
+                DataSrc::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |datasrc, element| datasrc.st2038_chain(pad, element, buffer),
+                )
+            })
+        };
+        let pad = builder
+            // Nothing to forward, events of these streams must not end up on the video
+            .event_function(|pad, _parent, event| {
+                gst_log!(CAT, obj: pad, "Got {:?} on {}", event.type_(), pad.name());
+                true
+            })
+            .build();
+        *sink_pad = Some(pad.clone());
+        drop(sink_pad);
+
+        gst_debug!(CAT, obj: element, "Adding {} pad", name_template);
+
+        pad.set_active(true).unwrap();
+        element.add_pad(&pad).unwrap();
+
+        Some(pad)
+    }
+
+    fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
+        gst_debug!(CAT, obj: element, "Releasing pad {}", pad.name());
+
+        for sink_pad in &[&self.caption_pad, &self.st2038_pad] {
+            let mut sink_pad = sink_pad.lock().unwrap();
+            if sink_pad.as_ref() == Some(pad) {
+                *sink_pad = None;
+            }
+        }
+
+        let _ = pad.set_active(false);
+        let _ = element.remove_pad(pad);
+    }
+
+    // SCTE-35 events as sent by `gst_mpegts_section_send_event` go out with the next frame
+    fn send_event(&self, element: &Self::Type, event: gst::Event) -> bool {
+        match parse_scte35_event(&event) {
+            Some(section) => self.push_scte35(element, section),
+            None => self.parent_send_event(element, event),
+        }
+    }
 }
 
 impl BaseSrcImpl for DataSrc {
     // Called when starting, so we can initialize all stream-related state to its defaults
     fn start(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        let keys = load_keys(&settings.key, settings.key_file.as_deref())?;
+        if !keys.is_empty() && !keys.contains(settings.key_id) {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["No key with id {}", settings.key_id]
+            ));
+        }
+        if !keys.is_empty() {
+            gst_info!(
+                CAT,
+                obj: element,
+                "Protecting messages with key {} ({:?})",
+                settings.key_id,
+                settings.key_mode
+            );
+        }
+
+        let compression = settings.compression.compression();
+        let dictionary = match settings.compression_dictionary {
+            Some(ref path) => fs::read(path).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Failed to read compression dictionary {}: {}", path, err]
+                )
+            })?,
+            None => Vec::new(),
+        };
+        if !dictionary.is_empty()
+            && !compression.map_or(false, |compression| compression.supports_dictionary())
+        {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Settings,
+                [
+                    "compression-dictionary is not supported with compression {:?}",
+                    settings.compression
+                ]
+            ));
+        }
+
         // Reset state
-        *self.state.lock().unwrap() = Default::default();
+        *self.state.lock().unwrap() = State {
+            encoder: MessageEncoder {
+                compression,
+                dictionary: Arc::new(dictionary),
+                keys,
+                key_id: settings.key_id,
+                key_mode: settings.key_mode,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // Left over from before the last `stop`
+        self.message_queue.lock().unwrap().forget_compressed();
+        self.set_flushing(false);
 
         gst_info!(CAT, obj: element, "Started");
 
@@ -175,29 +2204,70 @@ impl BaseSrcImpl for DataSrc {
     fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
         // Reset state
         *self.state.lock().unwrap() = Default::default();
+        self.set_flushing(true);
 
         gst_info!(CAT, obj: element, "Stopped");
 
         Ok(())
     }
 
+    // Only the formats of the carriage
+    fn caps(&self, _element: &Self::Type, filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+        let caps = video_caps(self.settings.lock().unwrap().carriage.formats());
+
+        Some(match filter {
+            Some(filter) => filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First),
+            None => caps,
+        })
+    }
+
+    // Closest to the mode, in the first format downstream takes
+    fn fixate(&self, element: &Self::Type, mut caps: gst::Caps) -> gst::Caps {
+        let mode = self.settings.lock().unwrap().mode.info();
+
+        caps.truncate();
+        {
+            let caps = caps.make_mut();
+            let s = caps.structure_mut(0).unwrap();
+            s.fixate_field_nearest_int("width", mode.width);
+            s.fixate_field_nearest_int("height", mode.height);
+            s.fixate_field_nearest_fraction("framerate", mode.fps);
+            if s.has_field("interlace-mode") {
+                s.fixate_field_str(
+                    "interlace-mode",
+                    if mode.interlaced {
+                        "interleaved"
+                    } else {
+                        "progressive"
+                    },
+                );
+            }
+        }
+
+        self.parent_fixate(element, caps)
+    }
+
     fn set_caps(&self, element: &Self::Type, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
         let info = gst_video::VideoInfo::from_caps(caps).map_err(|_| {
             gst::loggable_error!(CAT, "Failed to build `VideoInfo` from caps {}", caps)
         })?;
 
-        gst_debug!(CAT, obj: element, "Configuring for caps {}", caps);
+        // Frames are timestamped by their frame rate
+        if info.fps().numer() <= 0 || info.fps().denom() <= 0 {
+            return Err(gst::loggable_error!(
+                CAT,
+                "Variable frame rate in caps {} is not supported",
+                caps
+            ));
+        }
 
-        element.set_blocksize(info.width() * info.height() * 4);
+        gst_debug!(CAT, obj: element, "Configuring for caps {}", caps);
 
-        let mut state = self.state.lock().unwrap();
+        element.set_blocksize(info.size() as u32);
 
-        *state = State {
-            info: Some(info),
-            current_frame_num: state.current_frame_num,
-        };
+        self.state.lock().unwrap().info = Some(info);
 
-        drop(state);
+        element.notify("max-payload-size");
 
         let _ = element.post_message(gst::message::Latency::builder().src(element).build());
 
@@ -207,53 +2277,123 @@ impl BaseSrcImpl for DataSrc {
 
 impl PushSrcImpl for DataSrc {
     fn create(&self, element: &Self::Type) -> Result<gst::Buffer, gst::FlowError> {
-        let mut state = self.state.lock().unwrap();
-        let info = match state.info {
-            None => {
-                gst::element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
-                return Err(gst::FlowError::NotNegotiated);
-            }
-            Some(ref info) => info.clone(),
-        };
-
-        let input = {
-            self.message_queue
-                .lock()
-                .unwrap()
-                .pop_front()
-                .unwrap_or_else(|| "".to_string())
+        let (info, frame_num, mut encoder) = {
+            let mut state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => {
+                    gst::element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                    return Err(gst::FlowError::NotNegotiated);
+                }
+                Some(ref info) => info.clone(),
+            };
+            let frame_num = state.current_frame_num;
+            state.current_frame_num += 1;
+            (info, frame_num, state.encoder.clone())
         };
 
-        let buffer_size = (info.width() as usize) * (info.height() as usize) * 4;
+        let buffer_size = info.size();
 
-        let delta = (1000 / info.fps().to_integer()) as u64;
+        let settings = self.settings.lock().unwrap().clone();
+        let video_time_code = frame_time_code(&info, settings.timecode_start, frame_num);
+        let mut frame_encoder = FrameEncoder::with_coding(
+            frame_layout(&settings, &info),
+            FrameHeader {
+                timecode: video_time_code.as_ref().map(from_video_time_code),
+                crc: settings.crc,
+            },
+            settings.payload_coding.coding(),
+        );
 
         let mut buffer = gst::Buffer::with_size(buffer_size).unwrap();
         {
             let buffer = buffer.get_mut().unwrap();
 
-            buffer.set_pts(ClockTime::from_mseconds(state.current_frame_num * delta));
+            let pts = frame_pts(&info, frame_num);
+            let duration = frame_pts(&info, frame_num + 1) - pts;
+            buffer.set_pts(pts);
+            buffer.set_duration(duration);
+            buffer.set_flags(gst::BufferFlags::from_bits_truncate(
+                interlace_flags(&info).bits(),
+            ));
 
-            state.current_frame_num += 1;
+            if let Some(ref video_time_code) = video_time_code {
+                gst_video::VideoTimeCodeMeta::add(buffer, video_time_code);
+            }
 
             // Map the buffer writable and create the actual samples
             let mut map = buffer.map_writable().unwrap();
-            let mut data = map.as_mut_slice();
 
-            // Only if there is anything to send
-            if !input.is_empty() {
-                gst_info!(CAT, obj: element, "Received input and sending it out");
+            // State must not be locked here, need-data handlers may call send-data
+            let frame = FramePosition {
+                pts,
+                running_time: element
+                    .segment()
+                    .downcast_ref::<ClockTime>()
+                    .and_then(|segment| segment.to_running_time(pts)),
+                duration,
+                timecode: video_time_code.as_ref().map(from_video_time_code),
+            };
+            let messages = self.pop_messages(element, &frame_encoder, &frame, &mut encoder);
+            self.state.lock().unwrap().encoder.last_timestamp = encoder.last_timestamp;
 
-                let safe_input = convert_to_sdi_safe_payload(input.as_bytes());
-                data.put_u32(MAGIC_NUMBER);
-                data.put_u32(convert_without_0_and_255(safe_input.len() as u32));
-                data.put(&safe_input[..]);
-            } else {
-                data.put_u32(0);
+            if !messages.is_empty() {
+                gst_info!(
+                    CAT,
+                    obj: element,
+                    "Sending out {} queued messages",
+                    messages.len()
+                );
+
+                for message in &messages {
+                    if !frame_encoder.push(&message.record()) {
+                        gst::element_error!(
+                            element,
+                            gst::StreamError::Encode,
+                            [
+                                "Message of {} bytes on channel {} does not fit in the frame",
+                                message.data.len(),
+                                message.channel
+                            ]
+                        );
+                        return Err(gst::FlowError::Error);
+                    }
+                }
             }
-        }
 
-        drop(state);
+            match settings.carriage {
+                Carriage::Picture => {
+                    let encoded = if info.format() == gst_video::VideoFormat::V210 {
+                        // The zero magic number of empty frames would be reserved
+                        // samples, blank luma says as much
+                        let mut luma = crate::lines::blank_v210(&info);
+                        let encoded = if frame_encoder.is_empty() {
+                            Ok(0)
+                        } else {
+                            frame_encoder.encode(&mut luma)
+                        };
+                        crate::lines::write_v210(&info, &luma, map.as_mut_slice());
+                        encoded
+                    } else {
+                        if settings.line_margin > 0 {
+                            // Whatever the buffer held before could be taken for a
+                            // frame by a sync search
+                            map.as_mut_slice().fill(0);
+                        }
+                        frame_encoder.encode(map.as_mut_slice())
+                    };
+                    if let Err(err) = encoded {
+                        gst::element_error!(element, gst::StreamError::Encode, ["{}", err]);
+                        return Err(gst::FlowError::Error);
+                    }
+                }
+                Carriage::Levels => {
+                    // Pictures too small for even an empty frame carry nothing
+                    let mut data = vec![0; frame_encoder.layout().capacity()];
+                    let len = frame_encoder.encode(&mut data).unwrap_or(0);
+                    crate::levels::write_levels(&info, &data[..len], map.as_mut_slice());
+                }
+            }
+        }
 
         gst_debug!(
             CAT,
@@ -265,3 +2405,303 @@ impl PushSrcImpl for DataSrc {
         Ok(buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::sync::Once;
+
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            gst::init().unwrap();
+            crate::plugin_register_static().unwrap();
+        });
+    }
+
+    /// Messages `datasink` receives from `datasrc carriage=levels` in `format`, after
+    /// `videoconvert` converted it to `converted` and back
+    fn levels_round_trip(
+        format: gst_video::VideoFormat,
+        converted: gst_video::VideoFormat,
+    ) -> Vec<String> {
+        let pipeline = gst::parse_launch(&format!(
+            "datasrc name=src carriage=levels num-buffers=10 \
+             ! video/x-raw,format={},width=320,height=240,framerate=25/1 \
+             ! videoconvert ! video/x-raw,format={} ! videoconvert ! datasink name=sink",
+            format.to_str(),
+            converted.to_str()
+        ))
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+        let sink = pipeline.by_name("sink").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        sink.connect("data-received", false, move |args| {
+            let data = args[1].get::<String>().unwrap();
+            sender.lock().unwrap().send(data).unwrap();
+            None
+        })
+        .unwrap();
+
+        pipeline.set_state(gst::State::Paused).unwrap();
+        src.emit_by_name("send-data", &[&"Hello"]).unwrap();
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        let message = pipeline.bus().unwrap().timed_pop_filtered(
+            ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        pipeline.set_state(gst::State::Null).unwrap();
+        match message.as_ref().map(|message| message.view()) {
+            Some(gst::MessageView::Eos(..)) => (),
+            Some(gst::MessageView::Error(err)) => panic!("{}", err.error()),
+            _ => panic!("Timed out"),
+        }
+
+        receiver.try_iter().collect()
+    }
+
+    fn video_info(fps: gst::Fraction) -> gst_video::VideoInfo {
+        gst_video::VideoInfo::builder(gst_video::VideoFormat::Uyvy, 320, 240)
+            .fps(fps)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn frame_pts_works() {
+        init();
+
+        let info = video_info(gst::Fraction::new(25, 1));
+        assert_eq!(frame_pts(&info, 0), ClockTime::ZERO);
+        assert_eq!(frame_pts(&info, 1), ClockTime::from_mseconds(40));
+        assert_eq!(frame_pts(&info, 25 * 3600), ClockTime::from_seconds(3600));
+
+        // No rounding errors add up over a day of 29.97 fps
+        let info = video_info(gst::Fraction::new(30000, 1001));
+        assert_eq!(frame_pts(&info, 1), ClockTime::from_nseconds(33_366_666));
+        assert_eq!(frame_pts(&info, 30000), ClockTime::from_seconds(1001));
+        assert_eq!(
+            frame_pts(&info, 30000 * 86400),
+            ClockTime::from_seconds(1001 * 86400)
+        );
+    }
+
+    #[test]
+    fn schedule_matches_frames() {
+        let timecode = |frames| Timecode {
+            hours: 1,
+            minutes: 0,
+            seconds: 0,
+            frames,
+        };
+        let frame = FramePosition {
+            pts: ClockTime::from_mseconds(1040),
+            running_time: Some(ClockTime::from_mseconds(40)),
+            duration: ClockTime::from_mseconds(40),
+            timecode: Some(timecode(1)),
+        };
+        let ms = ClockTime::from_mseconds;
+
+        // Anything within the duration of the frame is in it
+        for &(at, ordering) in &[
+            (ms(0), Ordering::Less),
+            (ms(39), Ordering::Less),
+            (ms(40), Ordering::Equal),
+            (ms(79), Ordering::Equal),
+            (ms(80), Ordering::Greater),
+        ] {
+            assert_eq!(Schedule::RunningTime(at).cmp_frame(&frame), Some(ordering));
+            assert_eq!(
+                Schedule::Pts(at + ms(1000)).cmp_frame(&frame),
+                Some(ordering)
+            );
+        }
+
+        assert_eq!(
+            Schedule::Timecode(timecode(0)).cmp_frame(&frame),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Schedule::Timecode(timecode(1)).cmp_frame(&frame),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Schedule::Timecode(timecode(2)).cmp_frame(&frame),
+            Some(Ordering::Greater)
+        );
+
+        // Frames outside the segment or without timecode never match
+        let frame = FramePosition {
+            running_time: None,
+            timecode: None,
+            ..frame
+        };
+        assert_eq!(Schedule::RunningTime(ms(40)).cmp_frame(&frame), None);
+        assert_eq!(Schedule::Timecode(timecode(1)).cmp_frame(&frame), None);
+        assert_eq!(
+            Schedule::Pts(ms(1040)).cmp_frame(&frame),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        init();
+
+        let pipeline = gst::parse_launch(
+            "datasrc name=src max-queued-messages=5 num-buffers=3 \
+             ! video/x-raw,format=UYVY,width=320,height=240,framerate=25/1 ! fakesink",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let enough_data = Mutex::new(sender.clone());
+        src.connect("enough-data", false, move |_| {
+            enough_data.lock().unwrap().send(None).unwrap();
+            None
+        })
+        .unwrap();
+        let need_data = Mutex::new(sender);
+        src.connect("need-data", false, move |args| {
+            let length = args[1].get::<u32>().unwrap();
+            need_data.lock().unwrap().send(Some(length)).unwrap();
+            None
+        })
+        .unwrap();
+
+        // The oldest messages make room for new ones by default
+        for i in 0..20 {
+            let accepted = src
+                .emit_by_name("send-data", &[&format!("Message {}", i)])
+                .unwrap()
+                .unwrap()
+                .get::<bool>()
+                .unwrap();
+            assert!(accepted);
+            assert!(src.property("queued").unwrap().get::<u32>().unwrap() <= 5);
+        }
+        assert_eq!(src.property("queued").unwrap().get::<u32>().unwrap(), 5);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [None]);
+
+        pipeline.set_state(gst::State::Playing).unwrap();
+        let message = pipeline.bus().unwrap().timed_pop_filtered(
+            ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        pipeline.set_state(gst::State::Null).unwrap();
+        match message.as_ref().map(|message| message.view()) {
+            Some(gst::MessageView::Eos(..)) => (),
+            Some(gst::MessageView::Error(err)) => panic!("{}", err.error()),
+            _ => panic!("Timed out"),
+        }
+
+        // All of them went out with the first frame
+        assert_eq!(src.property("queued").unwrap().get::<u32>().unwrap(), 0);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [Some(DEFAULT_MAX_QUEUED_BYTES as u32)]
+        );
+    }
+
+    #[test]
+    fn timecodes_need_timecoded_frames() {
+        init();
+
+        // No frame of 25 fps has a timecode starting at frame 29
+        let pipeline = gst::parse_launch(
+            "datasrc name=src timecode-start=00:00:00:29 \
+             ! video/x-raw,format=UYVY,width=320,height=240,framerate=25/1 ! fakesink",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+
+        pipeline.set_state(gst::State::Paused).unwrap();
+        let (result, ..) = pipeline.state(ClockTime::from_seconds(10));
+        result.unwrap();
+
+        let accepted = src
+            .emit_by_name(
+                "send-data-at-timecode",
+                &[&0u32, &"Message", &"00:00:01:00"],
+            )
+            .unwrap()
+            .unwrap()
+            .get::<bool>()
+            .unwrap();
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        assert!(!accepted);
+        assert_eq!(src.property("queued").unwrap().get::<u32>().unwrap(), 0);
+    }
+
+    #[test]
+    fn block_spares_the_streaming_thread() {
+        init();
+
+        let pipeline = gst::parse_launch(
+            "datasrc name=src leaky-type=block max-queued-messages=1 num-buffers=3 \
+             ! video/x-raw,format=UYVY,width=320,height=240,framerate=25/1 ! fakesink",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let src = pipeline.by_name("src").unwrap();
+
+        // More than fit in the queue, from where only the queue is emptied
+        let sent = Mutex::new(false);
+        src.connect("need-data", false, move |args| {
+            let src = args[0].get::<gst::Element>().unwrap();
+            let mut sent = sent.lock().unwrap();
+            if !*sent {
+                *sent = true;
+                for i in 0..3 {
+                    src.emit_by_name("send-data", &[&format!("Message {}", i)])
+                        .unwrap();
+                }
+            }
+            None
+        })
+        .unwrap();
+
+        pipeline.set_state(gst::State::Playing).unwrap();
+        let message = pipeline.bus().unwrap().timed_pop_filtered(
+            ClockTime::from_seconds(10),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+        pipeline.set_state(gst::State::Null).unwrap();
+        match message.as_ref().map(|message| message.view()) {
+            Some(gst::MessageView::Eos(..)) => (),
+            Some(gst::MessageView::Error(err)) => panic!("{}", err.error()),
+            _ => panic!("Timed out"),
+        }
+    }
+
+    #[test]
+    fn levels_survive_videoconvert() {
+        init();
+
+        for &format in Carriage::Levels.formats() {
+            for &converted in Carriage::Levels.formats() {
+                assert_eq!(
+                    levels_round_trip(format, converted),
+                    ["Hello"],
+                    "{:?} converted to {:?}",
+                    format,
+                    converted
+                );
+            }
+        }
+    }
+}
@@ -1,39 +1,305 @@
-use once_cell::sync::Lazy;
+//! The frame format, independent of GStreamer. `FrameEncoder` and `FrameDecoder`
+//! read and write whole frames, the rest are the building blocks they use.
 
-use self::convert::Convert;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 
-mod convert;
+use memchr::{memchr, memchr3, memchr3_iter};
+
+pub use self::compression::{compress, decompress, Compression, DecompressError};
+pub use self::encryption::{DecryptError, InvalidKey, Keys, ENCRYPTION_OVERHEAD};
+pub use self::frame::{
+    DecodeError, DecodedFrame, FrameDecoder, FrameEncoder, FrameLayout, FrameTooSmall,
+    PayloadCoding,
+};
+pub use self::signing::{unix_time_us, ReplayWindow, Signed, VerifyError, SIGNING_OVERHEAD};
+
+pub mod captions;
+mod compression;
+mod encryption;
+mod frame;
+pub mod klv;
+pub mod levels;
+pub mod scte35;
+mod signing;
+pub mod st2038;
+pub mod stuffing;
+pub mod v210;
+pub mod variant;
 
 pub const MAGIC_NUMBER: u32 = 0xDEADB00B;
 
-static BASE_256_254: Lazy<Convert> = Lazy::new(|| Convert::new(256, 254));
-static BASE_254_256: Lazy<Convert> = Lazy::new(|| Convert::new(254, 256));
+/// Marks a frame whose payload holds a `FrameHeader` and length-prefixed records,
+/// see `pack_records`
+pub const PACKED_MAGIC_NUMBER: u32 = 0xDEADB00C;
+
+/// Same as `PACKED_MAGIC_NUMBER`, but the payload is stuffed instead of escaped,
+/// see `PayloadCoding`
+pub const STUFFED_MAGIC_NUMBER: u32 = 0xDEADB00D;
+
+/// Magic number and payload length in front of every payload
+pub const HEADER_SIZE: usize = 8;
+
+/// Length prefix, channel and flags in front of every record of a packed payload
+const RECORD_HEADER_SIZE: usize = 6;
+
+/// Record data is encrypted, see `Keys::encrypt`
+pub const RECORD_FLAG_ENCRYPTED: u8 = 0x01;
+
+/// Record data is signed, see `Keys::sign`
+pub const RECORD_FLAG_SIGNED: u8 = 0x02;
+
+/// Record data is compressed, see `compress`. Applied before encrypting or signing.
+pub const RECORD_FLAG_COMPRESSED: u8 = 0x04;
+
+/// Upper bits of the record flags, see `RecordType`
+pub const RECORD_TYPE_MASK: u8 = 0xf0;
+
+const RECORD_TYPE_SHIFT: u32 = 4;
+
+/// What the data of a record holds, kept in the upper bits of its flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    /// UTF-8 messages
+    Data,
+    /// SMPTE 336M KLV packets, see `klv`
+    Klv,
+    /// A single SCTE-35 splice info section, see `scte35`
+    Scte35,
+    /// Closed captions of a single frame, see `captions`
+    Captions,
+    /// A `GstStructure` serialized as a string
+    Structure,
+    /// A `GVariant` with its type, see `variant`
+    Variant,
+}
+
+impl RecordType {
+    /// Type set in `flags`, `None` for types this version doesn't know
+    pub fn from_flags(flags: u8) -> Option<RecordType> {
+        match flags >> RECORD_TYPE_SHIFT {
+            0 => Some(RecordType::Data),
+            1 => Some(RecordType::Klv),
+            2 => Some(RecordType::Scte35),
+            3 => Some(RecordType::Captions),
+            4 => Some(RecordType::Structure),
+            5 => Some(RecordType::Variant),
+            _ => None,
+        }
+    }
+
+    /// Flag bits to set for this type
+    pub fn flags(self) -> u8 {
+        let value = match self {
+            RecordType::Data => 0,
+            RecordType::Klv => 1,
+            RecordType::Scte35 => 2,
+            RecordType::Captions => 3,
+            RecordType::Structure => 4,
+            RecordType::Variant => 5,
+        };
+        value << RECORD_TYPE_SHIFT
+    }
+}
+
+/// Flags byte and timecode in front of the records of a packed payload
+const MAX_FRAME_HEADER_SIZE: usize = 5;
+
+/// CRC after the records of a packed payload, see `FrameHeader::crc`
+const CRC_SIZE: usize = 4;
 
-pub fn convert_without_0_and_255(x: u32) -> u32 {
-    let bytes = u32::to_le_bytes(x);
+const FRAME_FLAG_TIMECODE: u8 = 0x01;
+const FRAME_FLAG_CRC: u8 = 0x02;
 
-    let output_bytes: Vec<u8> = BASE_256_254.convert::<u8, u8>(&bytes);
-    u32::from_le_bytes([
-        output_bytes[0] + 1,
-        output_bytes[1] + 1,
-        output_bytes[2] + 1,
-        output_bytes[3] + 1,
-    ])
+/// Fields of a packed payload that apply to the whole frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub timecode: Option<Timecode>,
+    /// The payload ends in a `scte35::crc32_mpeg2` of everything in front of it,
+    /// frame header included. Receivers that don't know the flag skip it as a
+    /// truncated record.
+    pub crc: bool,
 }
 
-pub fn convert_back_with_0_and_255(x: u32) -> u32 {
-    let bytes = u32::to_le_bytes(x);
-    let bytes = &[bytes[0] - 1, bytes[1] - 1, bytes[2] - 1, bytes[3] - 1];
+impl FrameHeader {
+    /// A timecode with a field that doesn't fit in a byte is left out, see
+    /// `Timecode::to_bytes`
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(MAX_FRAME_HEADER_SIZE);
+        output.push(0);
+        if let Some(timecode) = self.timecode.and_then(Timecode::to_bytes) {
+            output[0] |= FRAME_FLAG_TIMECODE;
+            output.extend_from_slice(&timecode);
+        }
+        if self.crc {
+            output[0] |= FRAME_FLAG_CRC;
+        }
+
+        output
+    }
+
+    /// Parses the header in front of `payload`, returns it with the records after it
+    pub fn parse(payload: &[u8]) -> Option<(FrameHeader, &[u8])> {
+        let (&flags, mut payload) = payload.split_first()?;
+
+        let mut header = FrameHeader {
+            crc: flags & FRAME_FLAG_CRC != 0,
+            ..FrameHeader::default()
+        };
+        if flags & FRAME_FLAG_TIMECODE != 0 {
+            if payload.len() < 4 {
+                return None;
+            }
+            header.timecode = Some(Timecode {
+                hours: payload[0] as u32,
+                minutes: payload[1] as u32,
+                seconds: payload[2] as u32,
+                frames: payload[3] as u32,
+            });
+            payload = &payload[4..];
+        }
+
+        Some((header, payload))
+    }
+}
+
+/// A single message inside a packed payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub channel: u8,
+    pub flags: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    fn header(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let length = (self.data.len() as u32).to_be_bytes();
+        [
+            length[0],
+            length[1],
+            length[2],
+            length[3],
+            self.channel,
+            self.flags,
+        ]
+    }
 
-    let mut output_bytes: Vec<u8> = BASE_254_256.convert::<u8, u8>(bytes);
-    output_bytes.push(0);
+    pub fn record_type(&self) -> Option<RecordType> {
+        RecordType::from_flags(self.flags)
+    }
 
-    u32::from_le_bytes([
-        output_bytes[0],
-        output_bytes[1],
-        output_bytes[2],
-        output_bytes[3],
-    ])
+    /// Data authenticated along with encrypted or signed record data: the header of
+    /// its frame, as `FrameEncoder::header` and `DecodedFrame::header_bytes` give it,
+    /// and its channel and flags. Records can't be moved to other frames or channels.
+    pub fn aad(frame_header: &[u8], channel: u8, flags: u8) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(frame_header.len() + 2);
+        aad.extend_from_slice(frame_header);
+        aad.extend_from_slice(&[channel, flags]);
+        aad
+    }
+}
+
+/// SMPTE timecode of a frame, written as `hh:mm:ss:ff` (`hh:mm:ss;ff` for drop frame)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timecode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+}
+
+impl Timecode {
+    /// Hours, minutes, seconds and frames as a byte each, `None` if any of them
+    /// is above 255
+    pub fn to_bytes(self) -> Option<[u8; 4]> {
+        Some([
+            u8::try_from(self.hours).ok()?,
+            u8::try_from(self.minutes).ok()?,
+            u8::try_from(self.seconds).ok()?,
+            u8::try_from(self.frames).ok()?,
+        ])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTimecode(String);
+
+impl fmt::Display for InvalidTimecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid timecode {:?}, expected hh:mm:ss:ff", self.0)
+    }
+}
+
+impl FromStr for Timecode {
+    type Err = InvalidTimecode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTimecode(s.to_string());
+
+        let fields = s
+            .split(&[':', ';', '.'][..])
+            .map(|field| field.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match fields[..] {
+            // Frames are carried in a byte, no frame rate has more per second
+            [hours, minutes, seconds, frames]
+                if hours < 24 && minutes < 60 && seconds < 60 && frames <= u8::MAX as u32 =>
+            {
+                Ok(Timecode {
+                    hours,
+                    minutes,
+                    seconds,
+                    frames,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// Largest value `convert_without_0_and_255` can code, 4 digits of base 254.
+/// About 4.16 GB, which limits the payload of a frame, not the whole `u32` range.
+/// `FrameEncoder` never codes more, whatever the size of the frame.
+pub const MAX_SAFE_U32: u32 = 254 * 254 * 254 * 254 - 1;
+
+/// Codes `x` as 4 little endian digits of base 254, each shifted up by one so
+/// no byte is 0 or 255. This maps `0..=MAX_SAFE_U32` one to one onto the words
+/// without those bytes, `None` above it.
+pub fn convert_without_0_and_255(mut x: u32) -> Option<u32> {
+    if x > MAX_SAFE_U32 {
+        return None;
+    }
+
+    let mut digits = [0; 4];
+    for digit in &mut digits {
+        *digit = (x % 254) as u8 + 1;
+        x /= 254;
+    }
+
+    Some(u32::from_le_bytes(digits))
+}
+
+/// Reverses `convert_without_0_and_255`, `None` if any byte is 0 or 255
+pub fn convert_back_with_0_and_255(x: u32) -> Option<u32> {
+    x.to_le_bytes()
+        .iter()
+        .rev()
+        .try_fold(0, |value: u32, &digit| match digit {
+            1..=254 => Some(value * 254 + (digit - 1) as u32),
+            _ => None,
+        })
 }
 
 // 0 -> 254 1
@@ -41,64 +307,117 @@ pub fn convert_back_with_0_and_255(x: u32) -> u32 {
 // 255 -> 254 3
 const ESCAPE_BYTE: u8 = 254;
 
-pub fn convert_to_sdi_safe_payload(payload: &[u8]) -> Vec<u8> {
-    let mut output: Vec<u8> = Vec::with_capacity(payload.len());
-    for b in payload {
-        match b {
-            0 => {
-                output.push(ESCAPE_BYTE);
-                output.push(1);
-            }
-            &ESCAPE_BYTE => {
-                output.push(ESCAPE_BYTE);
-                output.push(2);
-            }
-            255 => {
-                output.push(ESCAPE_BYTE);
-                output.push(3);
-            }
-            value => {
-                output.push(*value);
-            }
+/// Length of `payload` after `convert_to_sdi_safe_payload`
+pub fn sdi_safe_payload_len(payload: &[u8]) -> usize {
+    payload.len() + memchr3_iter(0, ESCAPE_BYTE, 255, payload).count()
+}
+
+/// Length of `record` inside a packed payload after `convert_to_sdi_safe_payload`,
+/// including its header
+pub fn packed_record_len(record: &Record) -> usize {
+    sdi_safe_payload_len(&record.header()) + sdi_safe_payload_len(record.data)
+}
+
+/// Largest record guaranteed to fit in a frame of `frame_size` bytes,
+/// even if every byte of it has to be escaped.
+pub fn max_payload_size(frame_size: usize) -> usize {
+    frame_size
+        .saturating_sub(HEADER_SIZE + 2 * (MAX_FRAME_HEADER_SIZE + RECORD_HEADER_SIZE + CRC_SIZE))
+        / 2
+}
+
+/// Whether `record` fits in a packed frame of `frame_size` bytes on its own,
+/// whatever its `FrameHeader` is
+pub fn payload_fits(record: &Record, frame_size: usize) -> bool {
+    HEADER_SIZE + 2 * (MAX_FRAME_HEADER_SIZE + CRC_SIZE) + packed_record_len(record) <= frame_size
+}
+
+/// Concatenates `records`, each prefixed with its big endian `u32` length, channel and flags
+pub fn pack_records<'a>(records: impl IntoIterator<Item = Record<'a>>) -> Vec<u8> {
+    let mut output = Vec::new();
+    for record in records {
+        output.extend_from_slice(&record.header());
+        output.extend_from_slice(record.data);
+    }
+
+    output
+}
+
+/// Splits a payload built by `pack_records`, a truncated trailing record is ignored
+pub fn unpack_records(mut payload: &[u8]) -> Vec<Record<'_>> {
+    let mut records = Vec::new();
+    while payload.len() >= RECORD_HEADER_SIZE {
+        let length = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+        let channel = payload[4];
+        let flags = payload[5];
+        payload = &payload[RECORD_HEADER_SIZE..];
+        if length > payload.len() {
+            break;
         }
+        records.push(Record {
+            channel,
+            flags,
+            data: &payload[..length],
+        });
+        payload = &payload[length..];
     }
 
+    records
+}
+
+pub fn convert_to_sdi_safe_payload(payload: &[u8]) -> Vec<u8> {
+    let mut output = vec![0; sdi_safe_payload_len(payload)];
+    convert_to_sdi_safe_payload_into(payload, &mut output);
+
     output
 }
 
+/// Writes `payload` escaped like `convert_to_sdi_safe_payload` to the start of
+/// `output`, returns how many bytes were written.
+///
+/// Panics if `output` is shorter than `sdi_safe_payload_len(payload)`.
+pub fn convert_to_sdi_safe_payload_into(payload: &[u8], output: &mut [u8]) -> usize {
+    let mut written = 0;
+    let mut rest = payload;
+    // Everything between reserved bytes is copied at once
+    while let Some(i) = memchr3(0, ESCAPE_BYTE, 255, rest) {
+        output[written..written + i].copy_from_slice(&rest[..i]);
+        output[written + i] = ESCAPE_BYTE;
+        output[written + i + 1] = match rest[i] {
+            0 => 1,
+            ESCAPE_BYTE => 2,
+            _ => 3,
+        };
+        written += i + 2;
+        rest = &rest[i + 1..];
+    }
+    output[written..written + rest.len()].copy_from_slice(rest);
+
+    written + rest.len()
+}
+
+/// Reverses `convert_to_sdi_safe_payload`, panics on invalid escape sequences
 pub fn convert_from_sdi_safe_payload(payload: &[u8]) -> Vec<u8> {
+    try_convert_from_sdi_safe_payload(payload).expect("Unexpected escape number")
+}
+
+/// Reverses `convert_to_sdi_safe_payload`, `None` on invalid escape sequences
+pub fn try_convert_from_sdi_safe_payload(payload: &[u8]) -> Option<Vec<u8>> {
     let mut output: Vec<u8> = Vec::with_capacity(payload.len());
-    let mut escape_logic = false;
-    for b in payload {
-        if escape_logic {
-            match b {
-                1 => {
-                    output.push(0);
-                }
-                2 => {
-                    output.push(254);
-                }
-                3 => {
-                    output.push(255);
-                }
-                _ => {
-                    panic!("Unexpected escape number: {}", b);
-                }
-            }
-            escape_logic = false;
-        } else {
-            match b {
-                254 => {
-                    escape_logic = true;
-                }
-                value => {
-                    output.push(*value);
-                }
-            }
-        }
+    let mut rest = payload;
+    while let Some(i) = memchr(ESCAPE_BYTE, rest) {
+        output.extend_from_slice(&rest[..i]);
+        output.push(match rest.get(i + 1)? {
+            1 => 0,
+            2 => ESCAPE_BYTE,
+            3 => 255,
+            _ => return None,
+        });
+        rest = &rest[i + 2..];
     }
+    output.extend_from_slice(rest);
 
-    output
+    Some(output)
 }
 
 #[cfg(test)]
@@ -127,6 +446,168 @@ mod tests {
             convert_from_sdi_safe_payload(&convert_to_sdi_safe_payload(&large_payload)),
             large_payload
         );
+
+        let mut output = [42; 12];
+        assert_eq!(convert_to_sdi_safe_payload_into(&payload1, &mut output), 9);
+        assert_eq!(output, [254, 1, 2, 3, 4, 254, 2, 254, 3, 42, 42, 42]);
+
+        assert_eq!(try_convert_from_sdi_safe_payload(&[1, 254, 4]), None);
+        assert_eq!(try_convert_from_sdi_safe_payload(&[1, 254]), None);
+    }
+
+    #[test]
+    fn payload_size_limits_work() {
+        let payload = [0, 2, 3, 4, 254, 255];
+
+        assert_eq!(
+            sdi_safe_payload_len(&payload),
+            convert_to_sdi_safe_payload(&payload).len()
+        );
+
+        assert_eq!(max_payload_size(4), 0);
+        // Room is kept for the largest frame header and a CRC
+        assert_eq!(max_payload_size(48), 5);
+
+        let record = |data| Record {
+            channel: 0,
+            flags: 0,
+            data,
+        };
+        assert!(payload_fits(&record(&[0; 5]), 48));
+        assert!(!payload_fits(&record(&[0; 7]), 48));
+        // Header [0, 0, 0, 14, 0, 0] escapes to 11 bytes
+        assert!(payload_fits(&record(&[1; 14]), 51));
+        assert!(!payload_fits(&record(&[1; 15]), 51));
+
+        let records = [
+            Record {
+                channel: 1,
+                flags: 0,
+                data: &[1, 2, 3],
+            },
+            Record {
+                channel: 255,
+                flags: RECORD_FLAG_ENCRYPTED,
+                data: &[255],
+            },
+        ];
+        assert_eq!(
+            packed_record_len(&records[0]) + packed_record_len(&records[1]),
+            convert_to_sdi_safe_payload(&pack_records(records.iter().copied())).len()
+        );
+    }
+
+    #[test]
+    fn pack_and_unpack_records_works() {
+        let records = [
+            Record {
+                channel: 0,
+                flags: 0,
+                data: b"Hello",
+            },
+            Record {
+                channel: 7,
+                flags: RECORD_FLAG_ENCRYPTED,
+                data: b"",
+            },
+            Record {
+                channel: 2,
+                flags: 0,
+                data: &[0, 254, 255],
+            },
+        ];
+        let packed = pack_records(records.iter().copied());
+
+        assert_eq!(
+            &packed[..11],
+            &[0, 0, 0, 5, 0, 0, b'H', b'e', b'l', b'l', b'o']
+        );
+        assert_eq!(unpack_records(&packed), records);
+
+        assert_eq!(
+            unpack_records(&convert_from_sdi_safe_payload(
+                &convert_to_sdi_safe_payload(&packed)
+            )),
+            records
+        );
+
+        // Truncated records are dropped
+        assert_eq!(unpack_records(&packed[..packed.len() - 1]), &records[..2]);
+        assert!(unpack_records(&[0, 0]).is_empty());
+    }
+
+    #[test]
+    fn frame_header_works() {
+        let header = FrameHeader {
+            timecode: Some(Timecode {
+                hours: 23,
+                minutes: 59,
+                seconds: 0,
+                frames: 1,
+            }),
+            crc: false,
+        };
+
+        let mut payload = header.to_bytes();
+        assert_eq!(payload, [FRAME_FLAG_TIMECODE, 23, 59, 0, 1]);
+        assert!(sdi_safe_payload_len(&payload) <= 2 * MAX_FRAME_HEADER_SIZE);
+
+        payload.push(42);
+        assert_eq!(FrameHeader::parse(&payload), Some((header, &[42][..])));
+
+        assert_eq!(
+            FrameHeader::parse(&FrameHeader::default().to_bytes()),
+            Some((FrameHeader::default(), &[][..]))
+        );
+        assert_eq!(FrameHeader::parse(&[]), None);
+
+        let header = FrameHeader {
+            timecode: None,
+            crc: true,
+        };
+        assert_eq!(header.to_bytes(), [FRAME_FLAG_CRC]);
+        assert_eq!(
+            FrameHeader::parse(&header.to_bytes()),
+            Some((header, &[][..]))
+        );
+
+        // Rather than wrapping around to another frame
+        let header = FrameHeader {
+            timecode: Some(Timecode {
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+                frames: 300,
+            }),
+            crc: false,
+        };
+        assert_eq!(header.to_bytes(), [0]);
+        assert_eq!(FrameHeader::parse(&[FRAME_FLAG_TIMECODE, 1, 2]), None);
+    }
+
+    #[test]
+    fn timecode_parsing_works() {
+        let timecode = Timecode {
+            hours: 10,
+            minutes: 0,
+            seconds: 59,
+            frames: 24,
+        };
+
+        assert_eq!("10:00:59:24".parse(), Ok(timecode));
+        assert_eq!("10:00:59;24".parse(), Ok(timecode));
+        assert_eq!(timecode.to_string(), "10:00:59:24");
+
+        assert!("10:00:59".parse::<Timecode>().is_err());
+        assert!("10:60:00:00".parse::<Timecode>().is_err());
+        assert!("00:00:00:300".parse::<Timecode>().is_err());
+        assert_eq!(
+            "00:00:00:255".parse::<Timecode>().unwrap().to_bytes(),
+            Some([0, 0, 0, 255])
+        );
+        assert!("aa:00:00:00".parse::<Timecode>().is_err());
+
+        assert!("00:00:01:00".parse::<Timecode>().unwrap() > "00:00:00:24".parse().unwrap());
     }
 
     #[test]
@@ -134,32 +615,98 @@ mod tests {
         assert_eq!(u32::from_be_bytes([0, 0, 0, 255]), 255);
         assert_eq!(u32::from_le_bytes([255, 0, 0, 0]), 255);
         assert_eq!(
-            u32::to_be_bytes(convert_without_0_and_255(u32::from_be_bytes([0, 0, 0, 0]))),
+            u32::to_be_bytes(convert_without_0_and_255(u32::from_be_bytes([0, 0, 0, 0])).unwrap()),
             [1, 1, 1, 1]
         );
         assert_eq!(
-            u32::to_be_bytes(convert_without_0_and_255(u32::from_be_bytes([0, 0, 0, 1]))),
+            u32::to_be_bytes(convert_without_0_and_255(u32::from_be_bytes([0, 0, 0, 1])).unwrap()),
             [1, 1, 1, 2]
         );
         assert_eq!(
-            u32::to_be_bytes(convert_without_0_and_255(u32::from_be_bytes([
-                0, 0, 0, 255
-            ]))),
+            u32::to_be_bytes(
+                convert_without_0_and_255(u32::from_be_bytes([0, 0, 0, 255])).unwrap()
+            ),
             [1, 1, 2, 2]
         );
     }
 
     #[test]
     fn convert_back_with_zeros_works() {
-        assert_eq!(convert_back_with_0_and_255(convert_without_0_and_255(0)), 0);
-        assert_eq!(convert_back_with_0_and_255(convert_without_0_and_255(1)), 1);
+        let round_trip = |x| convert_back_with_0_and_255(convert_without_0_and_255(x)?);
+
+        for x in (0..=MAX_SAFE_U32).step_by(9973).chain(vec![
+            0,
+            1,
+            253,
+            254,
+            255,
+            345634,
+            MAX_SAFE_U32 - 1,
+            MAX_SAFE_U32,
+        ]) {
+            let converted = convert_without_0_and_255(x).unwrap();
+            assert!(converted.to_le_bytes().iter().all(|b| matches!(b, 1..=254)));
+            assert_eq!(round_trip(x), Some(x));
+        }
+
+        assert_eq!(convert_without_0_and_255(MAX_SAFE_U32), Some(0xfefefefe));
+        assert_eq!(convert_without_0_and_255(MAX_SAFE_U32 + 1), None);
+        assert_eq!(convert_without_0_and_255(u32::MAX), None);
+
+        assert_eq!(convert_back_with_0_and_255(0x01010101), Some(0));
+        assert_eq!(convert_back_with_0_and_255(0x01000101), None);
+        assert_eq!(convert_back_with_0_and_255(0x010101ff), None);
+    }
+
+    #[test]
+    fn convert_without_zeros_boundaries_work() {
+        // Around every carry into the next digit
+        for digits in 1..4 {
+            let carry = 254u32.pow(digits);
+            for &x in &[carry - 1, carry, carry + 1] {
+                let converted = convert_without_0_and_255(x).unwrap();
+                assert!(converted.to_le_bytes().iter().all(|b| matches!(b, 1..=254)));
+                assert_eq!(convert_back_with_0_and_255(converted), Some(x));
+            }
+
+            let mut below = [1; 4];
+            below[..digits as usize].fill(254);
+            assert_eq!(
+                convert_without_0_and_255(carry - 1),
+                Some(u32::from_le_bytes(below))
+            );
+            let mut above = [1; 4];
+            above[digits as usize] = 2;
+            assert_eq!(
+                convert_without_0_and_255(carry),
+                Some(u32::from_le_bytes(above))
+            );
+        }
+
+        // Around the largest value, above which nothing can be coded
         assert_eq!(
-            convert_back_with_0_and_255(convert_without_0_and_255(255)),
-            255
+            convert_back_with_0_and_255(convert_without_0_and_255(MAX_SAFE_U32 - 1).unwrap()),
+            Some(MAX_SAFE_U32 - 1)
         );
         assert_eq!(
-            convert_back_with_0_and_255(convert_without_0_and_255(345634)),
-            345634
+            convert_back_with_0_and_255(convert_without_0_and_255(MAX_SAFE_U32).unwrap()),
+            Some(MAX_SAFE_U32)
+        );
+        assert_eq!(convert_without_0_and_255(MAX_SAFE_U32 + 1), None);
+        assert_eq!(convert_back_with_0_and_255(0xfefefeff), None);
+        assert_eq!(convert_back_with_0_and_255(0xfffefefe), None);
+    }
+
+    #[test]
+    fn record_type_works() {
+        let flags = RecordType::Klv.flags() | RECORD_FLAG_ENCRYPTED | RECORD_FLAG_SIGNED;
+        assert_eq!(flags & !RECORD_TYPE_MASK, 0x03);
+        assert_eq!(RecordType::from_flags(flags), Some(RecordType::Klv));
+        assert_eq!(RecordType::from_flags(0x07), Some(RecordType::Data));
+        assert_eq!(
+            RecordType::from_flags(RecordType::Variant.flags()),
+            Some(RecordType::Variant)
         );
+        assert_eq!(RecordType::from_flags(0xf0), None);
     }
 }
@@ -0,0 +1,68 @@
+//! Closed captions are carried as a `CaptionType` byte followed by the caption data
+
+/// How the caption data of a record is packed, same as `GstVideoCaptionType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionType {
+    /// CEA-608 byte pairs of field 1
+    Cea608Raw,
+    /// CEA-608 byte triplets of both fields as in SMPTE ST 334-1 Annex A
+    Cea608S3341a,
+    /// CEA-708 `cc_data` triplets
+    Cea708Raw,
+    /// CEA-708 caption distribution packets
+    Cea708Cdp,
+}
+
+impl CaptionType {
+    pub fn from_byte(byte: u8) -> Option<CaptionType> {
+        match byte {
+            1 => Some(CaptionType::Cea608Raw),
+            2 => Some(CaptionType::Cea608S3341a),
+            3 => Some(CaptionType::Cea708Raw),
+            4 => Some(CaptionType::Cea708Cdp),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CaptionType::Cea608Raw => 1,
+            CaptionType::Cea608S3341a => 2,
+            CaptionType::Cea708Raw => 3,
+            CaptionType::Cea708Cdp => 4,
+        }
+    }
+}
+
+pub fn pack_captions(caption_type: CaptionType, data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(1 + data.len());
+    output.push(caption_type.to_byte());
+    output.extend_from_slice(data);
+    output
+}
+
+/// Reverses `pack_captions`, `None` for empty data or unknown caption types
+pub fn unpack_captions(data: &[u8]) -> Option<(CaptionType, &[u8])> {
+    let (&caption_type, data) = data.split_first()?;
+    Some((CaptionType::from_byte(caption_type)?, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_and_unpack_captions_works() {
+        let cdp = [0x96, 0x69, 0x10, 0x4f];
+        let packed = pack_captions(CaptionType::Cea708Cdp, &cdp);
+        assert_eq!(packed, [4, 0x96, 0x69, 0x10, 0x4f]);
+        assert_eq!(
+            unpack_captions(&packed),
+            Some((CaptionType::Cea708Cdp, &cdp[..]))
+        );
+
+        assert_eq!(unpack_captions(&[]), None);
+        assert_eq!(unpack_captions(&[0, 1, 2]), None);
+        assert_eq!(unpack_captions(&[5]), None);
+    }
+}
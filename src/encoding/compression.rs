@@ -0,0 +1,267 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io::Read;
+
+use flate2::{Compress, FlushCompress, Status};
+
+/// Algorithm and decompressed size in front of compressed data
+pub const COMPRESSION_HEADER_SIZE: usize = 5;
+
+/// Set in the algorithm byte if the data was compressed with a dictionary
+const DICTIONARY_BIT: u8 = 0x80;
+
+/// Most an LZ4 block can grow by, every extra byte of a match length adds 255
+const LZ4_MAX_RATIO: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Deflate = 1,
+    Zstd = 2,
+    Lz4 = 3,
+}
+
+impl Compression {
+    /// Deflate has no dictionary support without zlib
+    pub fn supports_dictionary(self) -> bool {
+        self != Compression::Deflate
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressError {
+    Truncated,
+    UnknownAlgorithm(u8),
+    MissingDictionary,
+    TooLarge(usize),
+    Corrupt,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecompressError::Truncated => write!(f, "Compressed record is truncated"),
+            DecompressError::UnknownAlgorithm(algorithm) => {
+                write!(f, "Unknown compression algorithm {}", algorithm)
+            }
+            DecompressError::MissingDictionary => {
+                write!(f, "Record was compressed with a dictionary")
+            }
+            DecompressError::TooLarge(size) => {
+                write!(f, "Record decompresses to too many bytes ({})", size)
+            }
+            DecompressError::Corrupt => write!(f, "Compressed record is corrupt"),
+        }
+    }
+}
+
+/// Compresses `data` with `compression` and `dictionary`, if not empty.
+/// `None` if that doesn't make it any smaller.
+pub fn compress(compression: Compression, dictionary: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let size: u32 = data.len().try_into().ok()?;
+    let use_dictionary = !dictionary.is_empty() && compression.supports_dictionary();
+
+    let compressed = match compression {
+        Compression::Deflate => {
+            let mut compressed = Vec::with_capacity(data.len());
+            let status = Compress::new(flate2::Compression::default(), false)
+                .compress_vec(data, &mut compressed, FlushCompress::Finish)
+                .ok()?;
+            // Ran out of room, which means it doesn't get any smaller
+            if status != Status::StreamEnd {
+                return None;
+            }
+            compressed
+        }
+        Compression::Zstd => zstd::block::Compressor::with_dict(if use_dictionary {
+            dictionary.to_vec()
+        } else {
+            Vec::new()
+        })
+        .compress(data, 0)
+        .ok()?,
+        Compression::Lz4 if use_dictionary => lz4_flex::block::compress_with_dict(data, dictionary),
+        Compression::Lz4 => lz4_flex::block::compress(data),
+    };
+
+    if COMPRESSION_HEADER_SIZE + compressed.len() >= data.len() {
+        return None;
+    }
+
+    let mut algorithm = compression as u8;
+    if use_dictionary {
+        algorithm |= DICTIONARY_BIT;
+    }
+
+    let mut output = Vec::with_capacity(COMPRESSION_HEADER_SIZE + compressed.len());
+    output.push(algorithm);
+    output.extend_from_slice(&size.to_be_bytes());
+    output.extend_from_slice(&compressed);
+
+    Some(output)
+}
+
+/// Reverses `compress`, refusing to decompress to more than `max_size` bytes
+pub fn decompress(
+    dictionary: &[u8],
+    data: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>, DecompressError> {
+    if data.len() < COMPRESSION_HEADER_SIZE {
+        return Err(DecompressError::Truncated);
+    }
+
+    let algorithm = data[0];
+    let size = u32::from_be_bytes(data[1..COMPRESSION_HEADER_SIZE].try_into().unwrap()) as usize;
+    let compressed = &data[COMPRESSION_HEADER_SIZE..];
+
+    if size > max_size {
+        return Err(DecompressError::TooLarge(size));
+    }
+
+    let use_dictionary = algorithm & DICTIONARY_BIT != 0;
+    if use_dictionary && dictionary.is_empty() {
+        return Err(DecompressError::MissingDictionary);
+    }
+
+    // Every algorithm is limited to `size` bytes of output. `size` comes from the
+    // sender, so output grows as it is decompressed instead of being allocated up
+    // front, apart from LZ4 which can't grow by much.
+    let decompressed = match algorithm & !DICTIONARY_BIT {
+        a if a == Compression::Deflate as u8 && !use_dictionary => {
+            read_limited(flate2::read::DeflateDecoder::new(compressed), size)?
+        }
+        a if a == Compression::Zstd as u8 => {
+            let decoder = zstd::stream::read::Decoder::with_dictionary(
+                compressed,
+                if use_dictionary { dictionary } else { &[] },
+            )
+            .map_err(|_| DecompressError::Corrupt)?;
+            read_limited(decoder.single_frame(), size)?
+        }
+        a if a == Compression::Lz4 as u8 && size > compressed.len() * LZ4_MAX_RATIO => {
+            return Err(DecompressError::Corrupt);
+        }
+        a if a == Compression::Lz4 as u8 && use_dictionary => {
+            lz4_flex::block::decompress_with_dict(compressed, size, dictionary)
+                .map_err(|_| DecompressError::Corrupt)?
+        }
+        a if a == Compression::Lz4 as u8 => {
+            lz4_flex::block::decompress(compressed, size).map_err(|_| DecompressError::Corrupt)?
+        }
+        _ => return Err(DecompressError::UnknownAlgorithm(algorithm)),
+    };
+
+    if decompressed.len() != size {
+        return Err(DecompressError::Corrupt);
+    }
+
+    Ok(decompressed)
+}
+
+/// Everything `reader` gives, or one byte more than `size` if it gives more
+fn read_limited(reader: impl Read, size: usize) -> Result<Vec<u8>, DecompressError> {
+    let mut decompressed = Vec::new();
+    reader
+        .take(size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| DecompressError::Corrupt)?;
+
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TELEMETRY: &[u8] = br#"[{"speed":12.5,"heading":270,"altitude":1500},
+        {"speed":12.6,"heading":270,"altitude":1501},
+        {"speed":12.5,"heading":271,"altitude":1502}]"#;
+
+    #[test]
+    fn compress_and_decompress_works() {
+        for &compression in &[Compression::Deflate, Compression::Zstd, Compression::Lz4] {
+            let compressed = compress(compression, &[], TELEMETRY).unwrap();
+            assert!(compressed.len() < TELEMETRY.len());
+            assert_eq!(compressed[0], compression as u8);
+            assert_eq!(
+                decompress(&[], &compressed, TELEMETRY.len()).unwrap(),
+                TELEMETRY
+            );
+
+            // Too short to get any smaller
+            assert_eq!(compress(compression, &[], b"Hello"), None);
+        }
+    }
+
+    #[test]
+    fn dictionaries_work() {
+        let dictionary = br#"{"speed":,"heading":,"altitude":}"#;
+        let message = br#"{"speed":12.5,"heading":270,"altitude":1500}"#;
+
+        for &compression in &[Compression::Zstd, Compression::Lz4] {
+            let compressed = compress(compression, dictionary, message).unwrap();
+            assert_eq!(compressed[0], compression as u8 | DICTIONARY_BIT);
+            assert_eq!(
+                decompress(dictionary, &compressed, message.len()).unwrap(),
+                &message[..]
+            );
+            assert_eq!(
+                decompress(&[], &compressed, message.len()),
+                Err(DecompressError::MissingDictionary)
+            );
+        }
+
+        // Deflate ignores it
+        let compressed = compress(Compression::Deflate, dictionary, TELEMETRY).unwrap();
+        assert_eq!(compressed[0], Compression::Deflate as u8);
+    }
+
+    #[test]
+    fn decompression_is_limited() {
+        let bomb = vec![b'a'; 1 << 20];
+        let compressed = compress(Compression::Zstd, &[], &bomb).unwrap();
+        assert_eq!(
+            decompress(&[], &compressed, 1000),
+            Err(DecompressError::TooLarge(1 << 20))
+        );
+
+        // Lying about the size doesn't help
+        for &compression in &[Compression::Deflate, Compression::Zstd, Compression::Lz4] {
+            let mut compressed = compress(compression, &[], &bomb).unwrap();
+            compressed[1..COMPRESSION_HEADER_SIZE].copy_from_slice(&1000u32.to_be_bytes());
+            assert_eq!(
+                decompress(&[], &compressed, 1000),
+                Err(DecompressError::Corrupt)
+            );
+        }
+
+        // Claiming more than there can be fails before anything is allocated
+        let mut compressed = compress(Compression::Lz4, &[], &bomb).unwrap();
+        assert_eq!(decompress(&[], &compressed, bomb.len()).unwrap(), bomb);
+        let size = (compressed.len() - COMPRESSION_HEADER_SIZE) * LZ4_MAX_RATIO + 1;
+        compressed[1..COMPRESSION_HEADER_SIZE].copy_from_slice(&(size as u32).to_be_bytes());
+        assert_eq!(
+            decompress(&[], &compressed, usize::MAX),
+            Err(DecompressError::Corrupt)
+        );
+
+        // Output that ends early or runs on doesn't match the size
+        for &compression in &[Compression::Deflate, Compression::Zstd] {
+            let mut compressed = compress(compression, &[], TELEMETRY).unwrap();
+            compressed[1..COMPRESSION_HEADER_SIZE].copy_from_slice(&(1u32 << 30).to_be_bytes());
+            assert_eq!(
+                decompress(&[], &compressed, usize::MAX),
+                Err(DecompressError::Corrupt)
+            );
+        }
+
+        assert_eq!(
+            decompress(&[], &[9, 0, 0, 0, 0], 1000),
+            Err(DecompressError::UnknownAlgorithm(9))
+        );
+        assert_eq!(
+            decompress(&[], &[1, 0, 0], 1000),
+            Err(DecompressError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// Key id, nonce and authentication tag added to every encrypted record
+pub const ENCRYPTION_OVERHEAD: usize = 1 + NONCE_SIZE + TAG_SIZE;
+
+/// 256 bit keys by key id, used for AES-256-GCM or HMAC-SHA256. Each use gets its
+/// own subkey derived with `derive_key`.
+///
+/// Written as `[<id>:]<64 hex digits>`, several keys separated by whitespace or
+/// commas. Keys without an id get id 0.
+#[derive(Clone, Default)]
+pub struct Keys(BTreeMap<u8, Secret>);
+
+#[derive(Clone)]
+struct Secret {
+    cipher: Aes256Gcm,
+    mac: Hmac<Sha256>,
+}
+
+/// HKDF-SHA256 (RFC 5869) output key of `ikm` for `info`, without salt. A single
+/// block of output is all a 256 bit key needs.
+fn derive_key(ikm: &[u8], info: &[u8]) -> [u8; KEY_SIZE] {
+    let mut extract = Hmac::<Sha256>::new_from_slice(&[0; KEY_SIZE]).expect("any key size works");
+    extract.update(ikm);
+    let prk = extract.finalize().into_bytes();
+
+    let mut expand = Hmac::<Sha256>::new_from_slice(&prk).expect("any key size works");
+    expand.update(info);
+    expand.update(&[1]);
+    expand.finalize().into_bytes().into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKey(String);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid key {:?}, expected [<id>:]<{} hex digits>",
+            self.0,
+            KEY_SIZE * 2
+        )
+    }
+}
+
+impl FromStr for Keys {
+    type Err = InvalidKey;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = BTreeMap::new();
+        for entry in s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|entry| !entry.is_empty())
+        {
+            // Don't leak the key itself into logs
+            let invalid = || InvalidKey(entry.chars().take(4).chain("...".chars()).collect());
+
+            let (id, key) = match entry.find(':') {
+                Some(i) => (
+                    entry[..i].parse::<u8>().map_err(|_| invalid())?,
+                    &entry[i + 1..],
+                ),
+                None => (0, entry),
+            };
+
+            let key: [u8; KEY_SIZE] = hex::decode(key)
+                .map_err(|_| invalid())?
+                .try_into()
+                .map_err(|_| invalid())?;
+
+            keys.insert(
+                id,
+                Secret {
+                    cipher: Aes256Gcm::new(&Key::from(derive_key(&key, b"enc"))),
+                    mac: Hmac::new_from_slice(&derive_key(&key, b"mac"))
+                        .expect("any key size works"),
+                },
+            );
+        }
+
+        Ok(Keys(keys))
+    }
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Keys")
+            .field(&self.0.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptError {
+    Truncated,
+    UnknownKey(u8),
+    Authentication(u8),
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecryptError::Truncated => write!(f, "Encrypted record is truncated"),
+            DecryptError::UnknownKey(id) => write!(f, "No key with id {}", id),
+            DecryptError::Authentication(id) => {
+                write!(f, "Authentication with key {} failed", id)
+            }
+        }
+    }
+}
+
+impl Keys {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, key_id: u8) -> bool {
+        self.0.contains_key(&key_id)
+    }
+
+    /// HMAC-SHA256 keyed with `key_id`, see `sign`
+    pub(super) fn mac(&self, key_id: u8) -> Option<Hmac<Sha256>> {
+        self.0.get(&key_id).map(|secret| secret.mac.clone())
+    }
+
+    /// Encrypts `plaintext` with a random nonce, `aad` is authenticated but not
+    /// included. `None` if there is no key `key_id`.
+    pub fn encrypt(&self, key_id: u8, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let cipher = &self.0.get(&key_id)?.cipher;
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+
+        let ciphertext = cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("encryption failure");
+
+        let mut output = Vec::with_capacity(plaintext.len() + ENCRYPTION_OVERHEAD);
+        output.push(key_id);
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&ciphertext);
+
+        Some(output)
+    }
+
+    /// Reverses `encrypt`, the same `aad` has to be given
+    pub fn decrypt(&self, aad: &[u8], data: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        if data.len() < ENCRYPTION_OVERHEAD {
+            return Err(DecryptError::Truncated);
+        }
+
+        let key_id = data[0];
+        let cipher = &self
+            .0
+            .get(&key_id)
+            .ok_or(DecryptError::UnknownKey(key_id))?
+            .cipher;
+        let (nonce, ciphertext) = data[1..].split_at(NONCE_SIZE);
+        let nonce: [u8; NONCE_SIZE] = nonce.try_into().unwrap();
+
+        cipher
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| DecryptError::Authentication(key_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_2: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    #[test]
+    fn parse_keys_works() {
+        let keys: Keys = format!("{}, 7:{}", KEY_1, KEY_2).parse().unwrap();
+        assert!(keys.contains(0));
+        assert!(keys.contains(7));
+        assert!(!keys.contains(1));
+
+        assert!("".parse::<Keys>().unwrap().is_empty());
+        assert!("0011".parse::<Keys>().is_err());
+        assert!(format!("256:{}", KEY_1).parse::<Keys>().is_err());
+        assert!(format!("1:{}", &KEY_1.replace('0', "x"))
+            .parse::<Keys>()
+            .is_err());
+    }
+
+    #[test]
+    fn derive_key_works() {
+        // RFC 5869 test case 3, first 32 bytes of the output key
+        assert_eq!(
+            hex::encode(derive_key(&[0x0b; 22], &[])),
+            "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d"
+        );
+
+        let key = hex::decode(KEY_1).unwrap();
+        assert_ne!(derive_key(&key, b"enc"), derive_key(&key, b"mac"));
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_works() {
+        let keys: Keys = format!("1:{} 2:{}", KEY_1, KEY_2).parse().unwrap();
+
+        let encrypted = keys.encrypt(2, &[3], b"Hello").unwrap();
+        assert_eq!(encrypted.len(), 5 + ENCRYPTION_OVERHEAD);
+        assert_eq!(encrypted[0], 2);
+        assert_eq!(keys.decrypt(&[3], &encrypted).unwrap(), b"Hello");
+
+        // Nonces are not reused
+        assert_ne!(keys.encrypt(2, &[3], b"Hello").unwrap(), encrypted);
+
+        assert_eq!(keys.encrypt(3, &[], b"Hello"), None);
+        assert_eq!(
+            keys.decrypt(&[4], &encrypted),
+            Err(DecryptError::Authentication(2))
+        );
+
+        let mut tampered = encrypted.clone();
+        tampered[ENCRYPTION_OVERHEAD] ^= 1;
+        assert_eq!(
+            keys.decrypt(&[3], &tampered),
+            Err(DecryptError::Authentication(2))
+        );
+
+        let only_key_1: Keys = format!("1:{}", KEY_1).parse().unwrap();
+        assert_eq!(
+            only_key_1.decrypt(&[3], &encrypted),
+            Err(DecryptError::UnknownKey(2))
+        );
+        assert_eq!(
+            keys.decrypt(&[3], &encrypted[..ENCRYPTION_OVERHEAD - 1]),
+            Err(DecryptError::Truncated)
+        );
+    }
+}
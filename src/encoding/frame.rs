@@ -0,0 +1,827 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use bytes::{Buf, BufMut};
+
+use super::scte35::crc32_mpeg2;
+use super::stuffing::{max_stuffed_len, max_unstuffed_len, stuff_payload_into, unstuff_payload};
+use super::{
+    convert_back_with_0_and_255, convert_to_sdi_safe_payload_into, convert_without_0_and_255,
+    max_payload_size, payload_fits, sdi_safe_payload_len, try_convert_from_sdi_safe_payload,
+    unpack_records, FrameHeader, Record, CRC_SIZE, HEADER_SIZE, MAGIC_NUMBER,
+    MAX_FRAME_HEADER_SIZE, MAX_SAFE_U32, PACKED_MAGIC_NUMBER, RECORD_HEADER_SIZE,
+    STUFFED_MAGIC_NUMBER,
+};
+
+/// Where the payload goes in a video frame: `lines` lines `stride` bytes apart,
+/// each filled from its start up to the `margin` at its right end. A picture moved
+/// right by up to the margin still holds every line whole, see `shifted`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameLayout {
+    /// Where the first line starts in the frame
+    pub offset: usize,
+    /// Bytes from the start of a line to the start of the next
+    pub stride: usize,
+    /// Bytes of picture in every line, the margin included
+    pub line_width: usize,
+    /// Bytes left out at the right end of every line
+    pub margin: usize,
+    pub lines: usize,
+}
+
+impl FrameLayout {
+    /// Frames of `size` bytes filled byte by byte from their start, whatever their
+    /// pixel format is
+    pub fn new(size: usize) -> Self {
+        Self::with_lines(0, size, size, 1)
+    }
+
+    /// Frames of `lines` lines of `line_width` bytes, `stride` bytes apart from
+    /// `offset` on
+    pub fn with_lines(offset: usize, stride: usize, line_width: usize, lines: usize) -> Self {
+        FrameLayout {
+            offset,
+            stride,
+            line_width,
+            margin: 0,
+            lines,
+        }
+    }
+
+    /// The same layout with `margin` bytes left out at the right end of every line
+    pub fn with_margin(self, margin: usize) -> Self {
+        FrameLayout { margin, ..self }
+    }
+
+    /// Bytes of frame data in every line
+    pub fn line_bytes(&self) -> usize {
+        self.line_width.saturating_sub(self.margin)
+    }
+
+    /// Bytes of frame data a frame holds
+    pub fn capacity(&self) -> usize {
+        self.line_bytes().saturating_mul(self.lines)
+    }
+
+    /// Layout of the same frames once the picture moved down by `lines` lines and
+    /// right by `bytes` bytes. What moved past the margin is lost.
+    pub fn shifted(&self, lines: usize, bytes: usize) -> Self {
+        FrameLayout {
+            offset: self
+                .offset
+                .saturating_add(lines.saturating_mul(self.stride))
+                .saturating_add(bytes),
+            stride: self.stride,
+            line_width: self.line_width.saturating_sub(bytes),
+            margin: self.margin.saturating_sub(bytes),
+            lines: self.lines.saturating_sub(lines),
+        }
+    }
+
+    /// Bytes of a frame the first `len` bytes of frame data reach up to
+    fn frame_len(&self, len: usize) -> usize {
+        let line_bytes = self.line_bytes();
+        if len == 0 {
+            return 0;
+        }
+        if line_bytes == 0 {
+            return usize::MAX;
+        }
+
+        let line = (len - 1) / line_bytes;
+        self.offset
+            .saturating_add(line.saturating_mul(self.stride))
+            .saturating_add(len - line * line_bytes)
+    }
+
+    /// Writes `data` into the lines of `frame`, `line_bytes` to each. The margins and
+    /// what `data` doesn't reach are left as they are.
+    ///
+    /// Panics if `frame` is too small for `data`.
+    pub fn write(&self, data: &[u8], frame: &mut [u8]) {
+        let line_bytes = self.line_bytes();
+        if line_bytes == 0 {
+            return;
+        }
+
+        for (line, data) in data.chunks(line_bytes).take(self.lines).enumerate() {
+            let start = self.offset + line * self.stride;
+            frame[start..start + data.len()].copy_from_slice(data);
+        }
+    }
+
+    /// Frame data in the lines of `frame`, as far as `frame` goes
+    pub fn read<'a>(&self, frame: &'a [u8]) -> Cow<'a, [u8]> {
+        let line_bytes = self.line_bytes();
+        let line_at = |line: usize| {
+            let start = self
+                .offset
+                .saturating_add(line.saturating_mul(self.stride))
+                .min(frame.len());
+            &frame[start..start.saturating_add(line_bytes).min(frame.len())]
+        };
+
+        match self.lines {
+            0 => Cow::Borrowed(&[]),
+            1 => Cow::Borrowed(line_at(0)),
+            lines => {
+                let mut data = Vec::with_capacity(self.capacity().min(frame.len()));
+                for line in (0..lines).map(line_at) {
+                    data.extend_from_slice(line);
+                    if line.len() < line_bytes {
+                        break;
+                    }
+                }
+                Cow::Owned(data)
+            }
+        }
+    }
+}
+
+/// Bytes of a frame of `frame_size` bytes a payload can take up, the length in
+/// front of it can't hold more than `MAX_SAFE_U32`
+fn usable_frame_size(frame_size: usize) -> usize {
+    frame_size.min(HEADER_SIZE + MAX_SAFE_U32 as usize)
+}
+
+/// How a packed payload is kept free of 0 and 255, told apart by the magic number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadCoding {
+    /// Escapes every 0, 254 and 255 into two bytes, see `convert_to_sdi_safe_payload`.
+    /// Understood by every receiver.
+    Escaped,
+    /// Adds at most one byte per 126, see `stuffing`
+    Stuffed,
+}
+
+impl PayloadCoding {
+    pub fn magic_number(self) -> u32 {
+        match self {
+            PayloadCoding::Escaped => PACKED_MAGIC_NUMBER,
+            PayloadCoding::Stuffed => STUFFED_MAGIC_NUMBER,
+        }
+    }
+
+    /// Room `bytes` of a packed payload take up, exact when escaping and
+    /// before stuffing, which is accounted for by `capacity`
+    fn coded_len(self, bytes: &[u8]) -> usize {
+        match self {
+            PayloadCoding::Escaped => sdi_safe_payload_len(bytes),
+            PayloadCoding::Stuffed => bytes.len(),
+        }
+    }
+
+    /// Most room the CRC at the end of a packed payload takes up, it isn't known
+    /// before the records are
+    fn crc_len(self) -> usize {
+        match self {
+            PayloadCoding::Escaped => 2 * CRC_SIZE,
+            PayloadCoding::Stuffed => CRC_SIZE,
+        }
+    }
+
+    /// Room for the packed payload in a frame of `frame_size` bytes, as counted
+    /// by `coded_len`
+    fn capacity(self, frame_size: usize) -> usize {
+        let space = usable_frame_size(frame_size).saturating_sub(HEADER_SIZE);
+        match self {
+            PayloadCoding::Escaped => space,
+            PayloadCoding::Stuffed => max_unstuffed_len(space),
+        }
+    }
+
+    /// Room `record` takes up in a frame, including its header
+    pub fn record_len(self, record: &Record) -> usize {
+        self.coded_len(&record.header()) + self.coded_len(record.data)
+    }
+
+    /// Most room a record with `len` bytes of data can take up, whatever its content
+    pub fn max_record_len(self, len: usize) -> usize {
+        match self {
+            PayloadCoding::Escaped => 2 * (RECORD_HEADER_SIZE + len),
+            PayloadCoding::Stuffed => RECORD_HEADER_SIZE + len,
+        }
+    }
+
+    /// Largest record guaranteed to fit in a frame of `frame_size` bytes,
+    /// whatever its content
+    pub fn max_payload_size(self, frame_size: usize) -> usize {
+        match self {
+            PayloadCoding::Escaped => max_payload_size(usable_frame_size(frame_size)),
+            PayloadCoding::Stuffed => self
+                .capacity(frame_size)
+                .saturating_sub(MAX_FRAME_HEADER_SIZE + RECORD_HEADER_SIZE + CRC_SIZE),
+        }
+    }
+
+    /// Whether `record` fits in a frame of `frame_size` bytes on its own,
+    /// whatever its `FrameHeader` is
+    pub fn payload_fits(self, record: &Record, frame_size: usize) -> bool {
+        match self {
+            PayloadCoding::Escaped => payload_fits(record, usable_frame_size(frame_size)),
+            PayloadCoding::Stuffed => {
+                MAX_FRAME_HEADER_SIZE + self.record_len(record) + CRC_SIZE
+                    <= self.capacity(frame_size)
+            }
+        }
+    }
+
+    /// Most bytes a packed payload of `len` bytes, which takes up `used` as counted
+    /// by `coded_len`, is coded into
+    fn max_coded_len(self, used: usize, len: usize) -> usize {
+        match self {
+            PayloadCoding::Escaped => used,
+            PayloadCoding::Stuffed => max_stuffed_len(len),
+        }
+    }
+
+    /// Codes `payload` into `output`, which has to be large enough for the
+    /// worst case
+    fn encode_into(self, payload: &[u8], output: &mut [u8]) -> usize {
+        match self {
+            PayloadCoding::Escaped => convert_to_sdi_safe_payload_into(payload, output),
+            PayloadCoding::Stuffed => stuff_payload_into(payload, output),
+        }
+    }
+
+    fn decode(self, payload: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            PayloadCoding::Escaped => try_convert_from_sdi_safe_payload(payload),
+            PayloadCoding::Stuffed => unstuff_payload(payload),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooSmall {
+    pub needed: usize,
+    pub size: usize,
+}
+
+impl fmt::Display for FrameTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Frame of {} bytes is too small, need {}",
+            self.size, self.needed
+        )
+    }
+}
+
+/// Packs records into a single frame
+#[derive(Debug, Clone)]
+pub struct FrameEncoder {
+    layout: FrameLayout,
+    coding: PayloadCoding,
+    /// Frame header followed by the records
+    packed: Vec<u8>,
+    header_len: usize,
+    /// Whether a CRC follows the records, see `FrameHeader::crc`
+    crc: bool,
+    /// Room taken up, the CRC included
+    used: usize,
+}
+
+impl FrameEncoder {
+    pub fn new(layout: FrameLayout, header: FrameHeader) -> Self {
+        Self::with_coding(layout, header, PayloadCoding::Escaped)
+    }
+
+    pub fn with_coding(layout: FrameLayout, header: FrameHeader, coding: PayloadCoding) -> Self {
+        let packed = header.to_bytes();
+        let mut used = coding.coded_len(&packed);
+        if header.crc {
+            used += coding.crc_len();
+        }
+
+        FrameEncoder {
+            layout,
+            coding,
+            header_len: packed.len(),
+            packed,
+            crc: header.crc,
+            used,
+        }
+    }
+
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    pub fn coding(&self) -> PayloadCoding {
+        self.coding
+    }
+
+    /// Frame header as written in front of the records
+    pub fn header(&self) -> &[u8] {
+        &self.packed[..self.header_len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packed.len() == self.header_len
+    }
+
+    /// Room left for records, as taken up by `PayloadCoding::record_len`
+    pub fn remaining(&self) -> usize {
+        self.coding
+            .capacity(self.layout.capacity())
+            .saturating_sub(self.used)
+    }
+
+    /// Adds `record` if it still fits, returns whether it did. Records that would
+    /// make the coded payload longer than `MAX_SAFE_U32`, the most the length in
+    /// front of it can hold, never fit, however large the layout is.
+    pub fn push(&mut self, record: &Record) -> bool {
+        let length = self.coding.record_len(record);
+        if length > self.remaining() {
+            return false;
+        }
+        // `capacity` keeps below it already, `encode` relies on it
+        let len = self.payload_len() + RECORD_HEADER_SIZE + record.data.len();
+        if self.coding.max_coded_len(self.used + length, len) > MAX_SAFE_U32 as usize {
+            return false;
+        }
+
+        self.packed.extend_from_slice(&record.header());
+        self.packed.extend_from_slice(record.data);
+        self.used += length;
+        true
+    }
+
+    /// Bytes of the packed payload before coding, the CRC included
+    fn payload_len(&self) -> usize {
+        self.packed.len() + if self.crc { CRC_SIZE } else { 0 }
+    }
+
+    /// Writes the frame into the lines of `frame` as laid out, the rest of it is
+    /// left as is. A frame without records only gets a zero magic number. Returns
+    /// how many bytes of frame data were written.
+    pub fn encode(&self, frame: &mut [u8]) -> Result<usize, FrameTooSmall> {
+        // Escaped lengths are exact and known already, stuffing is bounded
+        let needed = if self.is_empty() {
+            4
+        } else {
+            HEADER_SIZE + self.coding.max_coded_len(self.used, self.payload_len())
+        };
+        if needed > self.layout.capacity() || frame.len() < self.layout.frame_len(needed) {
+            return Err(FrameTooSmall {
+                needed: self.layout.frame_len(needed),
+                size: frame.len(),
+            });
+        }
+
+        if self.layout.lines == 1 {
+            return Ok(self.encode_data(&mut frame[self.layout.offset..]));
+        }
+        let mut data = vec![0; needed];
+        let len = self.encode_data(&mut data);
+        self.layout.write(&data[..len], frame);
+        Ok(len)
+    }
+
+    /// Writes the frame data to the start of `data`, which has room for it
+    fn encode_data(&self, mut data: &mut [u8]) -> usize {
+        if self.is_empty() {
+            data.put_u32(0);
+            return 4;
+        }
+
+        let payload = if self.crc {
+            let mut payload = self.packed.clone();
+            payload.extend_from_slice(&crc32_mpeg2(&self.packed).to_be_bytes());
+            Cow::Owned(payload)
+        } else {
+            Cow::Borrowed(&self.packed)
+        };
+
+        // Coded straight into the frame, the length goes in front of it after
+        let length = self.coding.encode_into(&payload, &mut data[HEADER_SIZE..]);
+        data.put_u32(self.coding.magic_number());
+        data.put_u32(
+            convert_without_0_and_255(length as u32)
+                .expect("push() keeps payloads below MAX_SAFE_U32"),
+        );
+        HEADER_SIZE + length
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidLength,
+    InvalidEscape,
+    InvalidHeader,
+    InvalidCrc,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidLength => write!(f, "Invalid payload length"),
+            DecodeError::InvalidEscape => write!(f, "Invalid escaping or stuffing of payload"),
+            DecodeError::InvalidHeader => write!(f, "Invalid frame header"),
+            DecodeError::InvalidCrc => write!(f, "Frame CRC doesn't match its payload"),
+        }
+    }
+}
+
+/// Payload read from a frame by `FrameDecoder`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFrame {
+    pub header: FrameHeader,
+    /// Bytes of frame data the frame took up, magic number and length included
+    pub len: usize,
+    payload: Vec<u8>,
+    packed: bool,
+    /// Where the records start in `payload`, after the frame header
+    records_start: usize,
+    /// Where they end, before the CRC if there is one
+    records_end: usize,
+}
+
+impl DecodedFrame {
+    /// Frame header as it was written in front of the records, empty for frames
+    /// from before records existed
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.payload[..self.records_start]
+    }
+
+    /// Records in the frame. Frames from before records existed hold a
+    /// single one on channel 0.
+    pub fn records(&self) -> Vec<Record<'_>> {
+        if self.packed {
+            unpack_records(&self.payload[self.records_start..self.records_end])
+        } else {
+            vec![Record {
+                channel: 0,
+                flags: 0,
+                data: &self.payload,
+            }]
+        }
+    }
+}
+
+/// Reads the records back out of frames written by `FrameEncoder`
+#[derive(Debug, Clone, Copy)]
+pub struct FrameDecoder {
+    layout: FrameLayout,
+}
+
+impl FrameDecoder {
+    pub fn new(layout: FrameLayout) -> Self {
+        FrameDecoder { layout }
+    }
+
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    /// `Ok(None)` if `frame` doesn't carry any data. Bytes outside of the lines of
+    /// the layout are ignored.
+    pub fn decode(&self, frame: &[u8]) -> Result<Option<DecodedFrame>, DecodeError> {
+        let data = self.layout.read(frame);
+        let mut data = &data[..];
+        if data.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let (packed, coding) = match data.get_u32() {
+            PACKED_MAGIC_NUMBER => (true, PayloadCoding::Escaped),
+            STUFFED_MAGIC_NUMBER => (true, PayloadCoding::Stuffed),
+            MAGIC_NUMBER => (false, PayloadCoding::Escaped),
+            _ => return Ok(None),
+        };
+
+        let length =
+            convert_back_with_0_and_255(data.get_u32()).ok_or(DecodeError::InvalidLength)? as usize;
+        if length > data.len() || length == 0 {
+            return Err(DecodeError::InvalidLength);
+        }
+
+        let raw_content = coding
+            .decode(&data[..length])
+            .ok_or(DecodeError::InvalidEscape)?;
+        if !packed {
+            return Ok(Some(DecodedFrame {
+                header: FrameHeader::default(),
+                len: HEADER_SIZE + length,
+                records_end: raw_content.len(),
+                payload: raw_content,
+                packed,
+                records_start: 0,
+            }));
+        }
+
+        let (header, records) =
+            FrameHeader::parse(&raw_content).ok_or(DecodeError::InvalidHeader)?;
+        let records_start = raw_content.len() - records.len();
+        let mut records_end = raw_content.len();
+        if header.crc {
+            // The CRC of everything up to and including it is 0
+            if records.len() < CRC_SIZE || crc32_mpeg2(&raw_content) != 0 {
+                return Err(DecodeError::InvalidCrc);
+            }
+            records_end -= CRC_SIZE;
+        }
+        Ok(Some(DecodedFrame {
+            header,
+            len: HEADER_SIZE + length,
+            payload: raw_content,
+            packed,
+            records_start,
+            records_end,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{pack_records, Timecode, FRAME_FLAG_TIMECODE, RECORD_FLAG_ENCRYPTED};
+
+    #[test]
+    fn encode_and_decode_frame_works() {
+        let layout = FrameLayout::new(64);
+        let header = FrameHeader {
+            timecode: Some(Timecode {
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 4,
+            }),
+            crc: false,
+        };
+        let records = [
+            Record {
+                channel: 0,
+                flags: 0,
+                data: b"Hello",
+            },
+            Record {
+                channel: 255,
+                flags: RECORD_FLAG_ENCRYPTED,
+                data: &[0, 254, 255],
+            },
+        ];
+
+        for &coding in &[PayloadCoding::Escaped, PayloadCoding::Stuffed] {
+            let mut encoder = FrameEncoder::with_coding(layout, header, coding);
+            let remaining = encoder.remaining();
+            for record in &records {
+                assert!(encoder.push(record));
+            }
+            assert_eq!(
+                encoder.remaining(),
+                remaining - coding.record_len(&records[0]) - coding.record_len(&records[1])
+            );
+            for record in &records {
+                assert!(coding.record_len(record) <= coding.max_record_len(record.data.len()));
+            }
+            assert!(!encoder.push(&Record {
+                channel: 0,
+                flags: 0,
+                data: &[0; 40],
+            }));
+
+            let mut frame = vec![0x10; layout.capacity()];
+            let written = encoder.encode(&mut frame).unwrap();
+            assert!(frame[written..].iter().all(|b| *b == 0x10));
+            assert_eq!(
+                u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]),
+                coding.magic_number()
+            );
+            assert_eq!(frame[layout.capacity() - 1], 0x10);
+            assert!(frame[4..]
+                .iter()
+                .take_while(|b| **b != 0x10)
+                .all(|b| !matches!(b, 0 | 255)));
+
+            let decoded = FrameDecoder::new(layout).decode(&frame).unwrap().unwrap();
+            assert_eq!(decoded.header, header);
+            assert_eq!(decoded.header_bytes(), encoder.header());
+            assert_eq!(
+                Record::aad(decoded.header_bytes(), 255, RECORD_FLAG_ENCRYPTED),
+                [FRAME_FLAG_TIMECODE, 1, 2, 3, 4, 255, RECORD_FLAG_ENCRYPTED]
+            );
+            assert_eq!(decoded.len, written);
+            assert_eq!(decoded.records(), records);
+
+            assert!(matches!(
+                encoder.encode(&mut frame[..20]),
+                Err(FrameTooSmall { size: 20, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn stuffed_frames_hold_more() {
+        let frame_size = 1000;
+        let layout = FrameLayout::new(frame_size);
+        let header = FrameHeader {
+            timecode: Some(Timecode {
+                hours: 1,
+                minutes: 1,
+                seconds: 1,
+                frames: 1,
+            }),
+            crc: true,
+        };
+
+        let escaped = PayloadCoding::Escaped.max_payload_size(frame_size);
+        let stuffed = PayloadCoding::Stuffed.max_payload_size(frame_size);
+        assert_eq!(escaped, max_payload_size(frame_size));
+        assert!(stuffed > 2 * escaped);
+
+        // Worst cases of both still fit
+        for &(coding, len) in &[
+            (PayloadCoding::Escaped, escaped),
+            (PayloadCoding::Stuffed, stuffed),
+        ] {
+            let data: Vec<u8> = [0, 254, 255, 1].iter().copied().cycle().take(len).collect();
+            let record = Record {
+                channel: 255,
+                flags: 0,
+                data: &data,
+            };
+            assert!(coding.payload_fits(&record, frame_size));
+
+            let mut encoder = FrameEncoder::with_coding(layout, header, coding);
+            assert!(encoder.push(&record));
+            encoder.encode(&mut vec![0; frame_size]).unwrap();
+        }
+
+        let data = vec![1; stuffed + 1];
+        let record = Record {
+            channel: 0,
+            flags: 0,
+            data: &data,
+        };
+        assert!(!PayloadCoding::Stuffed.payload_fits(&record, frame_size));
+    }
+
+    #[test]
+    fn crc_catches_corrupted_frames() {
+        let layout = FrameLayout::new(64);
+        let header = FrameHeader {
+            timecode: None,
+            crc: true,
+        };
+        let record = Record {
+            channel: 3,
+            flags: 0,
+            data: b"Hello",
+        };
+
+        for &coding in &[PayloadCoding::Escaped, PayloadCoding::Stuffed] {
+            let mut encoder = FrameEncoder::with_coding(layout, header, coding);
+            assert!(encoder.push(&record));
+            let mut frame = vec![0; layout.capacity()];
+            let written = encoder.encode(&mut frame).unwrap();
+
+            let decoder = FrameDecoder::new(layout);
+            let decoded = decoder.decode(&frame).unwrap().unwrap();
+            assert_eq!(decoded.header, header);
+            assert_eq!(decoded.len, written);
+            assert_eq!(decoded.records(), [record]);
+
+            // Still coded validly, but not what was sent
+            let at = frame.iter().position(|b| *b == b'H').unwrap();
+            frame[at] = b'J';
+            assert_eq!(decoder.decode(&frame), Err(DecodeError::InvalidCrc));
+        }
+
+        // Receivers that don't check it skip the CRC as a truncated record
+        let mut payload = header.to_bytes();
+        payload.extend_from_slice(&pack_records(std::iter::once(record)));
+        let (_, records) = FrameHeader::parse(&payload).unwrap();
+        let mut records = records.to_vec();
+        records.extend_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(unpack_records(&records), [record]);
+    }
+
+    #[test]
+    fn frames_can_be_laid_out_in_lines() {
+        // 10 lines of 20 bytes in a stride of 24 after 8 bytes of something else,
+        // leaving out 6 bytes at the end of each
+        let layout = FrameLayout::with_lines(8, 24, 20, 10).with_margin(6);
+        assert_eq!(layout.line_bytes(), 14);
+        assert_eq!(layout.capacity(), 140);
+
+        let header = FrameHeader {
+            timecode: None,
+            crc: true,
+        };
+        let data: Vec<u8> = (0..60).collect();
+        let record = Record {
+            channel: 1,
+            flags: 0,
+            data: &data,
+        };
+        let mut encoder = FrameEncoder::new(layout, header);
+        assert!(encoder.push(&record));
+
+        let size = 8 + 24 * 10;
+        let mut frame = vec![0x10; size];
+        let written = encoder.encode(&mut frame).unwrap();
+        assert!(written > 2 * layout.line_bytes());
+        for line in frame[8..].chunks(24) {
+            assert!(line[14..].iter().all(|b| *b == 0x10));
+        }
+        assert!(frame[..8].iter().all(|b| *b == 0x10));
+
+        let decoded = FrameDecoder::new(layout).decode(&frame).unwrap().unwrap();
+        assert_eq!(decoded.len, written);
+        assert_eq!(decoded.records(), [record]);
+
+        // Moved down a line and right by up to the margin, it's still whole
+        for &bytes in &[1, 6] {
+            let mut shifted = vec![0; size];
+            for (line, data) in frame[8..].chunks(24).take(9).enumerate() {
+                let start = 8 + (line + 1) * 24 + bytes;
+                shifted[start..8 + (line + 2) * 24 - 4].copy_from_slice(&data[..20 - bytes]);
+            }
+            let decoder = FrameDecoder::new(layout.shifted(1, bytes));
+            let decoded = decoder.decode(&shifted).unwrap().unwrap();
+            assert_eq!(decoded.records(), [record]);
+        }
+
+        // Lines have to be long enough for all of it
+        assert!(matches!(
+            encoder.encode(&mut frame[..100]),
+            Err(FrameTooSmall { size: 100, .. })
+        ));
+        let mut encoder = FrameEncoder::new(FrameLayout::with_lines(0, 24, 20, 3), header);
+        assert!(!encoder.push(&record));
+    }
+
+    #[test]
+    fn huge_frames_keep_to_the_length_limit() {
+        let layout = FrameLayout::new(usize::MAX);
+        let header = FrameHeader::default();
+        let data = [0; 16];
+        let record = Record {
+            channel: 0,
+            flags: 0,
+            data: &data,
+        };
+
+        for &coding in &[PayloadCoding::Escaped, PayloadCoding::Stuffed] {
+            let mut encoder = FrameEncoder::with_coding(layout, header, coding);
+            assert!(encoder.remaining() <= MAX_SAFE_U32 as usize);
+            assert!(coding.max_payload_size(layout.capacity()) <= MAX_SAFE_U32 as usize);
+
+            // Whatever fits is coded into no more than the length can hold
+            let capacity = coding.capacity(layout.capacity());
+            assert!(coding.max_coded_len(capacity, capacity) <= MAX_SAFE_U32 as usize);
+
+            assert!(encoder.push(&record));
+            assert!(matches!(
+                encoder.encode(&mut [0; 16]),
+                Err(FrameTooSmall { size: 16, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn decoding_bad_frames_works() {
+        let layout = FrameLayout::new(32);
+        let decoder = FrameDecoder::new(layout);
+
+        let mut frame = vec![0; layout.capacity()];
+        FrameEncoder::new(layout, FrameHeader::default())
+            .encode(&mut frame)
+            .unwrap();
+        assert_eq!(decoder.decode(&frame), Ok(None));
+        assert_eq!(decoder.decode(&[]), Ok(None));
+
+        // Legacy frames are a single message
+        let mut frame = vec![0; layout.capacity()];
+        frame[..4].copy_from_slice(&MAGIC_NUMBER.to_be_bytes());
+        frame[4..8].copy_from_slice(&convert_without_0_and_255(2).unwrap().to_be_bytes());
+        frame[8..10].copy_from_slice(b"Hi");
+        let decoded = decoder.decode(&frame).unwrap().unwrap();
+        assert_eq!(
+            decoded.records(),
+            [Record {
+                channel: 0,
+                flags: 0,
+                data: b"Hi",
+            }]
+        );
+
+        frame[8] = 254;
+        frame[9] = 9;
+        assert_eq!(decoder.decode(&frame), Err(DecodeError::InvalidEscape));
+
+        frame[4..8].copy_from_slice(&convert_without_0_and_255(100).unwrap().to_be_bytes());
+        assert_eq!(decoder.decode(&frame), Err(DecodeError::InvalidLength));
+        frame[4..8].copy_from_slice(&[1, 0, 1, 1]);
+        assert_eq!(decoder.decode(&frame), Err(DecodeError::InvalidLength));
+
+        frame[..4].copy_from_slice(&PACKED_MAGIC_NUMBER.to_be_bytes());
+        frame[4..8].copy_from_slice(&convert_without_0_and_255(2).unwrap().to_be_bytes());
+        // Timecode flag without a timecode
+        frame[8..10].copy_from_slice(&[1, 2]);
+        assert_eq!(decoder.decode(&frame), Err(DecodeError::InvalidHeader));
+    }
+}
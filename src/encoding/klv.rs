@@ -0,0 +1,219 @@
+use std::convert::TryInto;
+use std::fmt;
+
+/// Size of the universal label in front of every packet
+pub const KEY_SIZE: usize = 16;
+
+/// Universal label of the MISB ST 0601 UAS Datalink Local Set
+pub const MISB_0601_KEY: [u8; KEY_SIZE] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x02, 0x0b, 0x01, 0x01, 0x0e, 0x01, 0x03, 0x01, 0x01, 0x00, 0x00, 0x00,
+];
+
+/// Tag of the checksum item, which has to be the last one of a MISB ST 0601 set
+const MISB_0601_CHECKSUM_TAG: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KlvError {
+    Truncated,
+    InvalidLength,
+    MissingChecksum,
+    Checksum { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for KlvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KlvError::Truncated => write!(f, "KLV packet is truncated"),
+            KlvError::InvalidLength => write!(f, "Invalid BER length in KLV packet"),
+            KlvError::MissingChecksum => write!(f, "MISB ST 0601 packet has no checksum"),
+            KlvError::Checksum { expected, actual } => write!(
+                f,
+                "MISB ST 0601 checksum is {:#06x}, expected {:#06x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+/// A single SMPTE 336M key-length-value packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KlvPacket<'a> {
+    pub key: &'a [u8; KEY_SIZE],
+    pub value: &'a [u8],
+    /// The whole packet, as it was parsed
+    pub bytes: &'a [u8],
+}
+
+impl<'a> KlvPacket<'a> {
+    /// Checks the checksum of a MISB ST 0601 local set, other packets are
+    /// accepted as they are
+    pub fn check_misb_0601(&self) -> Result<(), KlvError> {
+        if self.key != &MISB_0601_KEY {
+            return Ok(());
+        }
+
+        // Tag, length and 16 bit value, covered by the checksum up to the value
+        let (covered, checksum) = match self.value.len().checked_sub(4) {
+            Some(i) if self.value[i..i + 2] == [MISB_0601_CHECKSUM_TAG, 2] => {
+                let end = self.bytes.len() - 2;
+                (&self.bytes[..end], &self.bytes[end..])
+            }
+            _ => return Err(KlvError::MissingChecksum),
+        };
+
+        let expected = misb_0601_checksum(covered);
+        let actual = u16::from_be_bytes(checksum.try_into().unwrap());
+        if expected != actual {
+            return Err(KlvError::Checksum { expected, actual });
+        }
+
+        Ok(())
+    }
+}
+
+/// 16 bit sum of big endian words, as defined by MISB ST 0601
+pub fn misb_0601_checksum(bytes: &[u8]) -> u16 {
+    bytes.iter().enumerate().fold(0u16, |sum, (i, &b)| {
+        let word = if i % 2 == 0 {
+            (b as u16) << 8
+        } else {
+            b as u16
+        };
+        sum.wrapping_add(word)
+    })
+}
+
+/// Parses a BER encoded length, returns it and how many bytes it took up
+pub fn parse_ber_length(data: &[u8]) -> Result<(usize, usize), KlvError> {
+    let (&first, rest) = data.split_first().ok_or(KlvError::Truncated)?;
+    if first & 0x80 == 0 {
+        return Ok((first as usize, 1));
+    }
+
+    // Long form, the low bits count the length bytes after it
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > std::mem::size_of::<usize>() {
+        return Err(KlvError::InvalidLength);
+    }
+    let bytes = rest.get(..count).ok_or(KlvError::Truncated)?;
+    let length = bytes
+        .iter()
+        .fold(0usize, |length, &b| (length << 8) | b as usize);
+
+    Ok((length, 1 + count))
+}
+
+/// Splits `data` into the KLV packets it is made of, fails unless all of it is
+/// whole packets
+pub fn parse_klv_packets(mut data: &[u8]) -> Result<Vec<KlvPacket<'_>>, KlvError> {
+    let mut packets = Vec::new();
+    while !data.is_empty() {
+        if data.len() < KEY_SIZE {
+            return Err(KlvError::Truncated);
+        }
+        let (length, length_size) = parse_ber_length(&data[KEY_SIZE..])?;
+        let value_start = KEY_SIZE + length_size;
+        if length > data.len() - value_start {
+            return Err(KlvError::Truncated);
+        }
+
+        let (bytes, rest) = data.split_at(value_start + length);
+        packets.push(KlvPacket {
+            key: bytes[..KEY_SIZE].try_into().unwrap(),
+            value: &bytes[value_start..],
+            bytes,
+        });
+        data = rest;
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Precision time stamp and platform heading items with a valid checksum
+    fn misb_0601_packet() -> Vec<u8> {
+        let mut packet = MISB_0601_KEY.to_vec();
+        packet.extend_from_slice(&[
+            0x12, 0x02, 0x08, 0x00, 0x04, 0x59, 0xf4, 0xa6, 0xaa, 0x4a, 0x42, 0x05, 0x02, 0x71,
+            0xc2, 0x01, 0x02,
+        ]);
+        let checksum = misb_0601_checksum(&packet);
+        packet.extend_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn parse_ber_length_works() {
+        assert_eq!(parse_ber_length(&[0x05, 0xff]), Ok((5, 1)));
+        assert_eq!(parse_ber_length(&[0x7f]), Ok((127, 1)));
+        assert_eq!(parse_ber_length(&[0x81, 0xc9]), Ok((201, 2)));
+        assert_eq!(parse_ber_length(&[0x82, 0x01, 0x00]), Ok((256, 3)));
+
+        assert_eq!(parse_ber_length(&[]), Err(KlvError::Truncated));
+        assert_eq!(parse_ber_length(&[0x82, 0x01]), Err(KlvError::Truncated));
+        assert_eq!(parse_ber_length(&[0x80]), Err(KlvError::InvalidLength));
+        assert_eq!(parse_ber_length(&[0xff]), Err(KlvError::InvalidLength));
+    }
+
+    #[test]
+    fn parse_klv_packets_works() {
+        let misb = misb_0601_packet();
+        let mut other = vec![0x42; KEY_SIZE];
+        other.extend_from_slice(&[0x81, 200]);
+        other.extend_from_slice(&[7; 200]);
+
+        let mut data = misb.clone();
+        data.extend_from_slice(&other);
+
+        let packets = parse_klv_packets(&data).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].key, &MISB_0601_KEY);
+        assert_eq!(packets[0].value.len(), 18);
+        assert_eq!(packets[0].bytes, &misb[..]);
+        assert_eq!(packets[1].value, &[7; 200][..]);
+        assert_eq!(packets[1].bytes, &other[..]);
+
+        assert!(parse_klv_packets(&[]).unwrap().is_empty());
+        assert_eq!(
+            parse_klv_packets(&data[..data.len() - 1]),
+            Err(KlvError::Truncated)
+        );
+        assert_eq!(
+            parse_klv_packets(&data[..KEY_SIZE - 1]),
+            Err(KlvError::Truncated)
+        );
+    }
+
+    #[test]
+    fn misb_0601_checksum_works() {
+        let mut packet = misb_0601_packet();
+        let parsed = parse_klv_packets(&packet).unwrap()[0];
+        assert_eq!(parsed.check_misb_0601(), Ok(()));
+
+        // Odd length, 0x1234 + 0x5678 + 0x9a00
+        assert_eq!(misb_0601_checksum(&[0x12, 0x34, 0x56, 0x78, 0x9a]), 0x02ac);
+
+        let last = packet.len() - 1;
+        packet[last] ^= 1;
+        let parsed = parse_klv_packets(&packet).unwrap()[0];
+        assert!(matches!(
+            parsed.check_misb_0601(),
+            Err(KlvError::Checksum { .. })
+        ));
+
+        // Checksum not last
+        let mut packet = MISB_0601_KEY.to_vec();
+        packet.extend_from_slice(&[0x06, 0x01, 0x02, 0x00, 0x00, 0x05, 0x00]);
+        let parsed = parse_klv_packets(&packet).unwrap()[0];
+        assert_eq!(parsed.check_misb_0601(), Err(KlvError::MissingChecksum));
+
+        // Only MISB ST 0601 sets are checked
+        let mut packet = vec![0x42; KEY_SIZE];
+        packet.extend_from_slice(&[0x01, 0x00]);
+        let parsed = parse_klv_packets(&packet).unwrap()[0];
+        assert_eq!(parsed.check_misb_0601(), Ok(()));
+    }
+}
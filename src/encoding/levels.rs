@@ -0,0 +1,239 @@
+//! Frames as grey levels that survive colour space conversion. Every pixel holds a
+//! symbol of `BITS_PER_SYMBOL` bits as one of `LEVELS`, after a training sequence
+//! of every level in turn. Conversions between RGB and YUV, limited and full range,
+//! scale and shift the levels, which the receiver learns from the training sequence
+//! before reading the symbols with thresholds halfway between levels.
+
+/// Sample values of the symbols, clear of the clipping of limited range
+pub const LEVELS: [u8; 4] = [32, 96, 160, 224];
+
+pub const BITS_PER_SYMBOL: usize = 2;
+
+/// Pixels each level takes up in the training sequence
+const TRAINING_RUN: usize = 16;
+
+/// Pixels at both ends of a training run left out, scaling and chroma filters blur
+/// them into their neighbours
+const TRAINING_EDGE: usize = 3;
+
+/// Pixels of the training sequence at the start of the picture
+pub const TRAINING_SIZE: usize = LEVELS.len() * TRAINING_RUN;
+
+/// Largest difference between samples of a training run
+const MAX_TRAINING_SPREAD: u8 = 12;
+
+/// Smallest difference between the levels of the training sequence
+const MIN_LEVEL_STEP: f32 = 24.0;
+
+/// Bytes a picture of `pixels` pixels holds
+pub fn capacity(pixels: usize) -> usize {
+    pixels.saturating_sub(TRAINING_SIZE) * BITS_PER_SYMBOL / 8
+}
+
+/// Writes the training sequence and `data` into the `samples` of a picture, one
+/// per pixel. Pixels after the data are set to the lowest level, pictures smaller
+/// than the training sequence only get as much of it as fits.
+pub fn write_levels(data: &[u8], samples: &mut [u8]) {
+    assert!(data.len() <= capacity(samples.len()));
+
+    let (training, symbols) = samples.split_at_mut(TRAINING_SIZE.min(samples.len()));
+    for (run, &level) in training.chunks_mut(TRAINING_RUN).zip(LEVELS.iter()) {
+        run.fill(level);
+    }
+
+    let symbols_per_byte = 8 / BITS_PER_SYMBOL;
+    let mask = (1 << BITS_PER_SYMBOL) - 1;
+    let mut symbols = symbols.iter_mut();
+    for &byte in data {
+        for i in (0..symbols_per_byte).rev() {
+            let symbol = byte >> (i * BITS_PER_SYMBOL) & mask;
+            *symbols.next().unwrap() = LEVELS[symbol as usize];
+        }
+    }
+    for sample in symbols {
+        *sample = LEVELS[0];
+    }
+}
+
+/// Mapping of the levels in the samples of a channel back to symbols,
+/// `sample = offset + gain * symbol`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub offset: f32,
+    pub gain: f32,
+}
+
+impl Calibration {
+    /// Learns the mapping from the training sequence at the start of `samples`,
+    /// `None` if it is not there
+    pub fn train(samples: &[u8]) -> Option<Calibration> {
+        if samples.len() < TRAINING_SIZE {
+            return None;
+        }
+
+        let mut means = [0f32; LEVELS.len()];
+        for (mean, run) in means
+            .iter_mut()
+            .zip(samples[..TRAINING_SIZE].chunks_exact(TRAINING_RUN))
+        {
+            let run = &run[TRAINING_EDGE..TRAINING_RUN - TRAINING_EDGE];
+            let min = *run.iter().min().unwrap();
+            let max = *run.iter().max().unwrap();
+            if max - min > MAX_TRAINING_SPREAD {
+                return None;
+            }
+            *mean = run.iter().map(|&sample| sample as f32).sum::<f32>() / run.len() as f32;
+        }
+        if means
+            .windows(2)
+            .any(|pair| pair[1] - pair[0] < MIN_LEVEL_STEP)
+        {
+            return None;
+        }
+
+        // Least squares fit of the means over the symbols
+        let n = means.len() as f32;
+        let symbol_mean = (n - 1.0) / 2.0;
+        let level_mean = means.iter().sum::<f32>() / n;
+        let (covariance, variance) =
+            means
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(covariance, variance), (symbol, &mean)| {
+                    let d = symbol as f32 - symbol_mean;
+                    (covariance + d * (mean - level_mean), variance + d * d)
+                });
+        let gain = covariance / variance;
+
+        Some(Calibration {
+            offset: level_mean - gain * symbol_mean,
+            gain,
+        })
+    }
+
+    /// Symbol `sample` stands for, not rounded yet
+    fn symbol(self, sample: u8) -> f32 {
+        (sample as f32 - self.offset) / self.gain
+    }
+}
+
+/// Reads the bytes after the training sequence back from the samples of one or
+/// more channels of the same pixels, with their calibration. Symbols are taken from
+/// the average of the channels.
+pub fn read_levels(channels: &[(&[u8], Calibration)]) -> Vec<u8> {
+    let pixels = channels
+        .iter()
+        .map(|(samples, _)| samples.len())
+        .min()
+        .unwrap_or(0);
+    let max_symbol = (LEVELS.len() - 1) as f32;
+
+    let mut data = Vec::with_capacity(capacity(pixels));
+    let mut byte = 0u8;
+    let mut bits = 0;
+    for pixel in TRAINING_SIZE..pixels {
+        let sum: f32 = channels
+            .iter()
+            .map(|(samples, calibration)| calibration.symbol(samples[pixel]))
+            .sum();
+        let symbol = (sum / channels.len() as f32).round().clamp(0.0, max_symbol) as u8;
+
+        byte = byte << BITS_PER_SYMBOL | symbol;
+        bits += BITS_PER_SYMBOL;
+        if bits == 8 {
+            data.push(byte);
+            byte = 0;
+            bits = 0;
+        }
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_levels_works() {
+        assert_eq!(capacity(TRAINING_SIZE + 8), 2);
+        assert_eq!(capacity(10), 0);
+
+        let data: Vec<u8> = (0..=255).collect();
+        let mut samples = vec![0; TRAINING_SIZE + 4 * data.len() + 5];
+        write_levels(&data, &mut samples);
+        assert_eq!(samples[..TRAINING_RUN], [LEVELS[0]; TRAINING_RUN]);
+        // 0x01 after 0x00
+        assert_eq!(
+            samples[TRAINING_SIZE + 4..TRAINING_SIZE + 8],
+            [32, 32, 32, 96]
+        );
+        assert_eq!(samples[samples.len() - 5..], [LEVELS[0]; 5]);
+
+        let calibration = Calibration::train(&samples).unwrap();
+        assert_eq!(
+            calibration,
+            Calibration {
+                offset: 32.0,
+                gain: 64.0
+            }
+        );
+        let read = read_levels(&[(&samples, calibration)]);
+        assert_eq!(read[..data.len()], data[..]);
+
+        // Full to limited range luma with rounding, and back to full range with
+        // different errors in every channel
+        let limited: Vec<u8> = samples
+            .iter()
+            .map(|&sample| (16.0 + sample as f32 * 219.0 / 255.0).round() as u8)
+            .collect();
+        let channels: Vec<Vec<u8>> = [-2i16, 0, 3]
+            .iter()
+            .map(|error| {
+                limited
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &sample)| {
+                        let full = (sample as f32 - 16.0) * 255.0 / 219.0;
+                        let noise = if i % 2 == 0 { *error } else { -error };
+                        (full.round() as i16 + noise).clamp(0, 255) as u8
+                    })
+                    .collect()
+            })
+            .collect();
+        let calibrated: Vec<(&[u8], Calibration)> = channels
+            .iter()
+            .map(|samples| (&samples[..], Calibration::train(samples).unwrap()))
+            .collect();
+        assert_eq!(read_levels(&calibrated)[..data.len()], data[..]);
+
+        let limited_calibration = Calibration::train(&limited).unwrap();
+        assert!((limited_calibration.gain - 64.0 * 219.0 / 255.0).abs() < 0.5);
+        assert_eq!(
+            read_levels(&[(&limited, limited_calibration)])[..data.len()],
+            data[..]
+        );
+    }
+
+    #[test]
+    fn training_is_checked() {
+        let mut samples = vec![0; TRAINING_SIZE + 40];
+        write_levels(&[0x1b; 10], &mut samples);
+        assert!(Calibration::train(&samples).is_some());
+        assert!(Calibration::train(&samples[..TRAINING_SIZE - 1]).is_none());
+
+        let mut small = vec![0; TRAINING_RUN + 2];
+        write_levels(&[], &mut small);
+        assert_eq!(small[TRAINING_RUN..], [LEVELS[1]; 2]);
+        assert!(Calibration::train(&small).is_none());
+
+        // Raw frames don't look like training sequences
+        let mut noisy = samples.clone();
+        noisy[TRAINING_RUN + TRAINING_RUN / 2] = 200;
+        assert!(Calibration::train(&noisy).is_none());
+
+        let mut flat = samples.clone();
+        flat[..TRAINING_SIZE].fill(128);
+        assert!(Calibration::train(&flat).is_none());
+    }
+}
@@ -0,0 +1,119 @@
+//! Checks on SCTE-35 `splice_info_section`s, which are carried as they are
+
+use std::convert::TryInto;
+use std::fmt;
+
+pub const SPLICE_INFO_TABLE_ID: u8 = 0xfc;
+
+/// Table id and section length in front of the section
+const SECTION_HEADER_SIZE: usize = 3;
+
+/// Fields up to `splice_command_type`, the descriptor loop length and CRC
+const MIN_SECTION_SIZE: usize = SECTION_HEADER_SIZE + 11 + 2 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scte35Error {
+    Truncated,
+    InvalidTableId(u8),
+    InvalidLength,
+    Crc { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for Scte35Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scte35Error::Truncated => write!(f, "Splice info section is truncated"),
+            Scte35Error::InvalidTableId(table_id) => {
+                write!(f, "Table id {:#04x} is not a splice info section", table_id)
+            }
+            Scte35Error::InvalidLength => write!(f, "Invalid splice info section length"),
+            Scte35Error::Crc { expected, actual } => write!(
+                f,
+                "Splice info section CRC is {:#010x}, expected {:#010x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+/// CRC-32 of MPEG-2 sections
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    data.iter().fold(0xffff_ffff, |mut crc, &b| {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Checks that `section` is a single whole `splice_info_section` with a valid CRC
+pub fn check_splice_info_section(section: &[u8]) -> Result<(), Scte35Error> {
+    if section.len() < MIN_SECTION_SIZE {
+        return Err(Scte35Error::Truncated);
+    }
+    if section[0] != SPLICE_INFO_TABLE_ID {
+        return Err(Scte35Error::InvalidTableId(section[0]));
+    }
+
+    let section_length = (u16::from_be_bytes([section[1], section[2]]) & 0x0fff) as usize;
+    if section_length != section.len() - SECTION_HEADER_SIZE {
+        return Err(Scte35Error::InvalidLength);
+    }
+
+    let (covered, crc) = section.split_at(section.len() - 4);
+    let expected = crc32_mpeg2(covered);
+    let actual = u32::from_be_bytes(crc.try_into().unwrap());
+    if expected != actual {
+        return Err(Scte35Error::Crc { expected, actual });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // splice_null()
+    const SPLICE_NULL: [u8; 20] = [
+        0xfc, 0x30, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xf0, 0x00, 0x00, 0x00,
+        0x00, 0x7a, 0x4f, 0xbf, 0xff,
+    ];
+
+    #[test]
+    fn check_splice_info_section_works() {
+        assert_eq!(check_splice_info_section(&SPLICE_NULL), Ok(()));
+        assert_eq!(crc32_mpeg2(&SPLICE_NULL), 0);
+
+        assert_eq!(
+            check_splice_info_section(&SPLICE_NULL[..19]),
+            Err(Scte35Error::Truncated)
+        );
+
+        let mut section = SPLICE_NULL.to_vec();
+        section.push(0);
+        assert_eq!(
+            check_splice_info_section(&section),
+            Err(Scte35Error::InvalidLength)
+        );
+
+        let mut section = SPLICE_NULL;
+        section[0] = 0x02;
+        assert_eq!(
+            check_splice_info_section(&section),
+            Err(Scte35Error::InvalidTableId(0x02))
+        );
+
+        let mut section = SPLICE_NULL;
+        section[5] = 0x01;
+        assert!(matches!(
+            check_splice_info_section(&section),
+            Err(Scte35Error::Crc { .. })
+        ));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::Mac;
+
+use super::encryption::Keys;
+
+const TIMESTAMP_SIZE: usize = 8;
+const SIGNATURE_SIZE: usize = 32;
+
+/// Key id, timestamp and signature added to every signed record
+pub const SIGNING_OVERHEAD: usize = 1 + TIMESTAMP_SIZE + SIGNATURE_SIZE;
+
+/// Microseconds since the UNIX epoch, as used for signing timestamps
+pub fn unix_time_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    Truncated,
+    UnknownKey(u8),
+    Signature(u8),
+    Replayed(u8),
+    OutsideWindow(u8),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::Truncated => write!(f, "Signed record is truncated"),
+            VerifyError::UnknownKey(id) => write!(f, "No key with id {}", id),
+            VerifyError::Signature(id) => write!(f, "Signature with key {} is invalid", id),
+            VerifyError::Replayed(id) => {
+                write!(f, "Record signed with key {} was received before", id)
+            }
+            VerifyError::OutsideWindow(id) => {
+                write!(
+                    f,
+                    "Record signed with key {} is outside the replay window",
+                    id
+                )
+            }
+        }
+    }
+}
+
+/// Contents of a record whose signature checked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signed<'a> {
+    pub key_id: u8,
+    pub timestamp: u64,
+    pub payload: &'a [u8],
+}
+
+impl Keys {
+    /// Prefixes `payload` with `key_id` and `timestamp` and appends an HMAC-SHA256
+    /// over all of it, `aad` is signed but not included. `None` if there is no
+    /// key `key_id`.
+    pub fn sign(&self, key_id: u8, aad: &[u8], timestamp: u64, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut mac = self.mac(key_id)?;
+
+        let mut output = Vec::with_capacity(payload.len() + SIGNING_OVERHEAD);
+        output.push(key_id);
+        output.extend_from_slice(&timestamp.to_be_bytes());
+        output.extend_from_slice(payload);
+
+        mac.update(aad);
+        mac.update(&output);
+        output.extend_from_slice(&mac.finalize().into_bytes());
+
+        Some(output)
+    }
+
+    /// Checks the signature added by `sign`, the same `aad` has to be given
+    pub fn verify<'a>(&self, aad: &[u8], data: &'a [u8]) -> Result<Signed<'a>, VerifyError> {
+        if data.len() < SIGNING_OVERHEAD {
+            return Err(VerifyError::Truncated);
+        }
+
+        let (signed, signature) = data.split_at(data.len() - SIGNATURE_SIZE);
+        let key_id = signed[0];
+        let mut mac = self.mac(key_id).ok_or(VerifyError::UnknownKey(key_id))?;
+        mac.update(aad);
+        mac.update(signed);
+        mac.verify(signature)
+            .map_err(|_| VerifyError::Signature(key_id))?;
+
+        Ok(Signed {
+            key_id,
+            timestamp: u64::from_be_bytes(signed[1..1 + TIMESTAMP_SIZE].try_into().unwrap()),
+            payload: &signed[1 + TIMESTAMP_SIZE..],
+        })
+    }
+}
+
+/// Rejects signed records that are not newer than the last one accepted with
+/// the same key, so a recording can't be played back into a receiver
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow {
+    last_timestamps: BTreeMap<u8, u64>,
+}
+
+impl ReplayWindow {
+    /// Also rejects records more than `window` microseconds away from `now`,
+    /// unless `window` is 0
+    pub fn check(&mut self, signed: &Signed, now: u64, window: u64) -> Result<(), VerifyError> {
+        if window > 0
+            && (signed.timestamp.saturating_add(window) < now
+                || signed.timestamp > now.saturating_add(window))
+        {
+            return Err(VerifyError::OutsideWindow(signed.key_id));
+        }
+
+        if let Some(&last) = self.last_timestamps.get(&signed.key_id) {
+            if signed.timestamp <= last {
+                return Err(VerifyError::Replayed(signed.key_id));
+            }
+        }
+
+        self.last_timestamps.insert(signed.key_id, signed.timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_2: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    #[test]
+    fn sign_and_verify_works() {
+        let keys: Keys = format!("1:{} 2:{}", KEY_1, KEY_2).parse().unwrap();
+
+        let signed = keys.sign(2, &[3], 1000, b"Hello").unwrap();
+        assert_eq!(signed.len(), 5 + SIGNING_OVERHEAD);
+        assert_eq!(
+            keys.verify(&[3], &signed),
+            Ok(Signed {
+                key_id: 2,
+                timestamp: 1000,
+                payload: b"Hello",
+            })
+        );
+
+        assert_eq!(keys.sign(3, &[], 1000, b"Hello"), None);
+        assert_eq!(keys.verify(&[4], &signed), Err(VerifyError::Signature(2)));
+
+        let mut tampered = signed.clone();
+        tampered[SIGNING_OVERHEAD - SIGNATURE_SIZE] ^= 1;
+        assert_eq!(keys.verify(&[3], &tampered), Err(VerifyError::Signature(2)));
+
+        // Re-signed with another key id
+        let mut tampered = signed.clone();
+        tampered[0] = 1;
+        assert_eq!(keys.verify(&[3], &tampered), Err(VerifyError::Signature(1)));
+
+        let only_key_1: Keys = format!("1:{}", KEY_1).parse().unwrap();
+        assert_eq!(
+            only_key_1.verify(&[3], &signed),
+            Err(VerifyError::UnknownKey(2))
+        );
+        assert_eq!(
+            keys.verify(&[3], &signed[..SIGNING_OVERHEAD - 1]),
+            Err(VerifyError::Truncated)
+        );
+    }
+
+    #[test]
+    fn replay_window_works() {
+        let signed = |key_id, timestamp| Signed {
+            key_id,
+            timestamp,
+            payload: &[],
+        };
+
+        let mut window = ReplayWindow::default();
+        assert_eq!(window.check(&signed(1, 1000), 1000, 0), Ok(()));
+        assert_eq!(
+            window.check(&signed(1, 1000), 1000, 0),
+            Err(VerifyError::Replayed(1))
+        );
+        assert_eq!(
+            window.check(&signed(1, 999), 1000, 0),
+            Err(VerifyError::Replayed(1))
+        );
+        assert_eq!(window.check(&signed(1, 1001), 1000, 0), Ok(()));
+        // Keys are tracked separately
+        assert_eq!(window.check(&signed(2, 500), 1000, 0), Ok(()));
+
+        assert_eq!(window.check(&signed(1, 1100), 1200, 100), Ok(()));
+        assert_eq!(
+            window.check(&signed(1, 1101), 1202, 100),
+            Err(VerifyError::OutsideWindow(1))
+        );
+        assert_eq!(
+            window.check(&signed(1, 1303), 1202, 100),
+            Err(VerifyError::OutsideWindow(1))
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! SMPTE ST 2038 ancillary data packets, ANC packets as MPEG-TS carries them. Every
+//! packet is bit packed as 6 zero bits, the `c_not_y_channel_flag`, an 11 bit line
+//! number and 12 bit horizontal offset, followed by the 10 bit DID, SDID, data
+//! count, user data words and checksum of the ANC packet, padded with 1 bits to
+//! the next byte. Packets can be followed by 0xff stuffing bytes.
+
+use std::fmt;
+
+/// Words an ANC packet takes up in a line besides its user data words: the
+/// ancillary data flag, DID, SDID, data count and checksum
+const PACKET_OVERHEAD: usize = 7;
+
+pub const MAX_USER_DATA_WORDS: usize = 255;
+
+/// Largest line number 11 bits hold
+pub const MAX_LINE: u16 = 0x7ff;
+
+/// Largest horizontal offset 12 bits hold
+pub const MAX_HORIZONTAL_OFFSET: u16 = 0xfff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum St2038Error {
+    Truncated,
+    InvalidStart,
+    Parity,
+    Checksum,
+    OutOfRange,
+}
+
+impl fmt::Display for St2038Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            St2038Error::Truncated => write!(f, "ST 2038 packet is truncated"),
+            St2038Error::InvalidStart => write!(f, "ST 2038 packet does not start with 0 bits"),
+            St2038Error::Parity => write!(f, "Invalid parity in ST 2038 packet"),
+            St2038Error::Checksum => write!(f, "Invalid ST 2038 packet checksum"),
+            St2038Error::OutOfRange => write!(
+                f,
+                "ST 2038 packets go past line {} or horizontal offset {}",
+                MAX_LINE, MAX_HORIZONTAL_OFFSET
+            ),
+        }
+    }
+}
+
+/// `byte` with b8 as its even parity and b9 as the inverse of b8
+fn with_parity(byte: u8) -> u16 {
+    let parity = (byte.count_ones() % 2) as u16;
+    byte as u16 | parity << 8 | (parity ^ 1) << 9
+}
+
+/// The 8 data bits of `word`, `None` if its parity bits are wrong
+fn without_parity(word: u16) -> Option<u8> {
+    let byte = word as u8;
+    if word & 0x3ff == with_parity(byte) {
+        Some(byte)
+    } else {
+        None
+    }
+}
+
+/// Sum of b0 to b8 of `words` with b9 as the inverse of b8
+fn checksum(words: &[u16]) -> u16 {
+    let sum = words
+        .iter()
+        .fold(0u16, |sum, word| sum.wrapping_add(word & 0x1ff))
+        & 0x1ff;
+    sum | (!sum >> 8 & 1) << 9
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct St2038Packet {
+    /// Whether the packet is in the chroma samples rather than the luma ones
+    pub c_not_y: bool,
+    pub line: u16,
+    pub horizontal_offset: u16,
+    pub did: u8,
+    pub sdid: u8,
+    pub data: Vec<u8>,
+}
+
+struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    bits: u32,
+    len: u32,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, value: u16, len: u32) {
+        for i in (0..len).rev() {
+            self.bits = self.bits << 1 | (value >> i & 1) as u32;
+            self.len += 1;
+            if self.len == 8 {
+                self.output.push(self.bits as u8);
+                self.bits = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Pads with 1 bits to the next byte
+    fn align(&mut self) {
+        while self.len != 0 {
+            self.write(1, 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, len: u32) -> Result<u16, St2038Error> {
+        if self.pos + len as usize > self.input.len() * 8 {
+            return Err(St2038Error::Truncated);
+        }
+
+        let mut value = 0;
+        for _ in 0..len {
+            let bit = self.input[self.pos / 8] >> (7 - self.pos % 8) & 1;
+            value = value << 1 | bit as u16;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) / 8 * 8;
+    }
+}
+
+impl St2038Packet {
+    /// Appends the packet to `output`, panics if it has more than
+    /// `MAX_USER_DATA_WORDS` bytes of data or its position is past `MAX_LINE` or
+    /// `MAX_HORIZONTAL_OFFSET`
+    pub fn write(&self, output: &mut Vec<u8>) {
+        assert!(self.data.len() <= MAX_USER_DATA_WORDS);
+        assert!(self.line <= MAX_LINE && self.horizontal_offset <= MAX_HORIZONTAL_OFFSET);
+
+        let mut words = vec![
+            with_parity(self.did),
+            with_parity(self.sdid),
+            with_parity(self.data.len() as u8),
+        ];
+        words.extend(self.data.iter().map(|&b| with_parity(b)));
+        words.push(checksum(&words));
+
+        let mut writer = BitWriter {
+            output,
+            bits: 0,
+            len: 0,
+        };
+        writer.write(0, 6);
+        writer.write(self.c_not_y as u16, 1);
+        writer.write(self.line, 11);
+        writer.write(self.horizontal_offset, 12);
+        for word in words {
+            writer.write(word, 10);
+        }
+        writer.align();
+    }
+}
+
+/// Splits `data` into luma packets of `did` and `sdid`, laid out from `line` on
+/// one after the other in lines of `samples` words, as many as fit in each. Fails
+/// if they go past `MAX_LINE` or `MAX_HORIZONTAL_OFFSET`.
+pub fn packetize(
+    did: u8,
+    sdid: u8,
+    mut data: &[u8],
+    line: u16,
+    samples: usize,
+) -> Result<Vec<St2038Packet>, St2038Error> {
+    let mut packets = Vec::new();
+    let mut line = line as usize;
+    let mut horizontal_offset = 0;
+    while !data.is_empty() && samples > PACKET_OVERHEAD {
+        let len = data
+            .len()
+            .min(MAX_USER_DATA_WORDS)
+            .min((samples - horizontal_offset).saturating_sub(PACKET_OVERHEAD));
+        if len == 0 {
+            line += 1;
+            horizontal_offset = 0;
+            continue;
+        }
+        if line > MAX_LINE as usize || horizontal_offset > MAX_HORIZONTAL_OFFSET as usize {
+            return Err(St2038Error::OutOfRange);
+        }
+
+        packets.push(St2038Packet {
+            c_not_y: false,
+            line: line as u16,
+            horizontal_offset: horizontal_offset as u16,
+            did,
+            sdid,
+            data: data[..len].to_vec(),
+        });
+        data = &data[len..];
+        horizontal_offset += PACKET_OVERHEAD + len;
+    }
+
+    Ok(packets)
+}
+
+/// Packets in `data`, up to the first stuffing byte
+pub fn parse_st2038_packets(data: &[u8]) -> Result<Vec<St2038Packet>, St2038Error> {
+    let mut reader = BitReader {
+        input: data,
+        pos: 0,
+    };
+    let mut packets = Vec::new();
+    while reader.pos < data.len() * 8 && data[reader.pos / 8] != 0xff {
+        if reader.read(6)? != 0 {
+            return Err(St2038Error::InvalidStart);
+        }
+        let c_not_y = reader.read(1)? == 1;
+        let line = reader.read(11)?;
+        let horizontal_offset = reader.read(12)?;
+
+        let mut words = Vec::new();
+        let mut word = || -> Result<u8, St2038Error> {
+            let word = reader.read(10)?;
+            words.push(word);
+            without_parity(word).ok_or(St2038Error::Parity)
+        };
+        let did = word()?;
+        let sdid = word()?;
+        let count = word()?;
+        let data = (0..count)
+            .map(|_| word())
+            .collect::<Result<Vec<u8>, St2038Error>>()?;
+
+        if reader.read(10)? != checksum(&words) {
+            return Err(St2038Error::Checksum);
+        }
+        reader.align();
+
+        packets.push(St2038Packet {
+            c_not_y,
+            line,
+            horizontal_offset,
+            did,
+            sdid,
+            data,
+        });
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parity_works() {
+        assert_eq!(with_parity(0x00), 0x200);
+        assert_eq!(with_parity(0x01), 0x101);
+        assert_eq!(with_parity(0xff), 0x2ff);
+        assert_eq!(without_parity(0x101), Some(0x01));
+        assert_eq!(without_parity(0x001), None);
+        // DID 0x50 + SDID 0x01 + DC 0x02 + UDW 0x00 + UDW 0xff
+        assert_eq!(checksum(&[0x250, 0x101, 0x102, 0x200, 0x2ff]), 0x152);
+    }
+
+    #[test]
+    fn write_and_parse_works() {
+        let packet = St2038Packet {
+            c_not_y: false,
+            line: 9,
+            horizontal_offset: 0,
+            did: 0x50,
+            sdid: 0x01,
+            data: vec![0x00, 0xff],
+        };
+        let mut data = Vec::new();
+        packet.write(&mut data);
+        // 30 bits of position, 6 words and 6 bits of alignment
+        assert_eq!(data.len(), 12);
+        assert_eq!(data[..4], [0x00, 0x02, 0x40, 0x02]);
+        assert_eq!(data[11] & 0x3f, 0x3f);
+
+        data.extend_from_slice(&[0xff; 4]);
+        assert_eq!(parse_st2038_packets(&data), Ok(vec![packet.clone()]));
+
+        data[10] ^= 0x40;
+        assert_eq!(parse_st2038_packets(&data), Err(St2038Error::Checksum));
+        assert_eq!(
+            parse_st2038_packets(&data[..8]),
+            Err(St2038Error::Truncated)
+        );
+        assert_eq!(
+            parse_st2038_packets(&[0x80]),
+            Err(St2038Error::InvalidStart)
+        );
+        assert_eq!(parse_st2038_packets(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn packetize_works() {
+        let data: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        let packets = packetize(0x50, 0x01, &data, 9, 1920).unwrap();
+        // 8 packets fill up the first line
+        assert_eq!(packets.len(), 13);
+        assert_eq!((packets[1].line, packets[1].horizontal_offset), (9, 262));
+        assert_eq!((packets[7].line, packets[7].data.len()), (9, 79));
+        assert_eq!((packets[8].line, packets[8].horizontal_offset), (10, 0));
+
+        let mut output = Vec::new();
+        for packet in &packets {
+            packet.write(&mut output);
+        }
+        let parsed = parse_st2038_packets(&output).unwrap();
+        assert_eq!(parsed, packets);
+        assert_eq!(
+            parsed
+                .into_iter()
+                .flat_map(|packet| packet.data)
+                .collect::<Vec<_>>(),
+            data
+        );
+    }
+
+    #[test]
+    fn packetize_checks_range() {
+        let data = vec![0x42; 3000];
+        let packets = packetize(0x50, 0x01, &data, MAX_LINE - 1, 1920).unwrap();
+        assert_eq!(packets.last().unwrap().line, MAX_LINE);
+        assert_eq!(
+            packetize(0x50, 0x01, &data, MAX_LINE, 1920),
+            Err(St2038Error::OutOfRange)
+        );
+        assert_eq!(
+            packetize(0x50, 0x01, &data, u16::MAX, 1920),
+            Err(St2038Error::OutOfRange)
+        );
+
+        // Lines of 8K are too wide for the last packets
+        let data = vec![0x42; 10000];
+        assert!(packetize(0x50, 0x01, &data, 9, 7680 / 2).is_ok());
+        assert_eq!(
+            packetize(0x50, 0x01, &data, 9, 7680),
+            Err(St2038Error::OutOfRange)
+        );
+
+        // Lines too short for a single packet hold nothing
+        assert_eq!(packetize(0x50, 0x01, &data, 9, 7), Ok(vec![]));
+    }
+}
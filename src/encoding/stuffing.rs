@@ -0,0 +1,149 @@
+//! COBS-like stuffing that removes both 0 and 255. Every block is a code byte
+//! followed by a run of bytes without them:
+//!
+//! - `1 + 2 * n` (`n` up to 125): `n` bytes, then a 0
+//! - `2 + 2 * n`: `n` bytes, then a 255
+//! - 253: 126 bytes, nothing after them
+//!
+//! The terminator of the last block is dropped, so at most one byte is added
+//! per 126, plus one.
+
+use memchr::memchr2;
+
+/// Longest run in a single block
+const MAX_RUN: usize = 126;
+
+/// Code of a block with a full run and no terminator
+const FULL_RUN: u8 = 253;
+
+/// Length of a payload of `len` bytes after `stuff_payload`, at most
+pub fn max_stuffed_len(len: usize) -> usize {
+    len + len / MAX_RUN + 1
+}
+
+/// Longest payload that is guaranteed to stuff into `space` bytes
+pub fn max_unstuffed_len(space: usize) -> usize {
+    // Every full run of `MAX_RUN` bytes takes up one more
+    let space = match space.checked_sub(1) {
+        Some(space) => space,
+        None => return 0,
+    };
+    let full_runs = space / (MAX_RUN + 1);
+    let rest = space % (MAX_RUN + 1);
+    full_runs * MAX_RUN + rest.min(MAX_RUN - 1)
+}
+
+/// Codes `payload` so it has no 0 or 255 bytes
+pub fn stuff_payload(payload: &[u8]) -> Vec<u8> {
+    let mut output = vec![0; max_stuffed_len(payload.len())];
+    let len = stuff_payload_into(payload, &mut output);
+    output.truncate(len);
+
+    output
+}
+
+/// Writes `payload` stuffed like `stuff_payload` to the start of `output`,
+/// returns how many bytes were written.
+///
+/// Panics if `output` is shorter than `max_stuffed_len(payload.len())`.
+pub fn stuff_payload_into(payload: &[u8], output: &mut [u8]) -> usize {
+    let mut written = 0;
+    let mut rest = payload;
+    loop {
+        let window = &rest[..rest.len().min(MAX_RUN)];
+        let (code, len) = match memchr2(0, 255, window) {
+            Some(len) => (1 + 2 * len as u8 + (rest[len] == 255) as u8, len),
+            None if rest.len() >= MAX_RUN => (FULL_RUN, MAX_RUN),
+            None => (1 + 2 * rest.len() as u8, rest.len()),
+        };
+
+        output[written] = code;
+        output[written + 1..written + 1 + len].copy_from_slice(&rest[..len]);
+        written += 1 + len;
+
+        rest = match code {
+            FULL_RUN => &rest[MAX_RUN..],
+            _ if len < rest.len() => &rest[len + 1..],
+            // Last block, without a terminator
+            _ => return written,
+        };
+    }
+}
+
+/// Reverses `stuff_payload`, `None` on invalid blocks
+pub fn unstuff_payload(mut payload: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(payload.len());
+    while let Some((&code, rest)) = payload.split_first() {
+        let (len, terminator) = match code {
+            FULL_RUN => (MAX_RUN, None),
+            1..=252 if code % 2 == 1 => ((code as usize - 1) / 2, Some(0)),
+            1..=252 => ((code as usize - 2) / 2, Some(255)),
+            _ => return None,
+        };
+        if len > rest.len() {
+            return None;
+        }
+
+        output.extend_from_slice(&rest[..len]);
+        payload = &rest[len..];
+        if let Some(terminator) = terminator.filter(|_| !payload.is_empty()) {
+            output.push(terminator);
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stuff_and_unstuff_payload_works() {
+        assert_eq!(stuff_payload(&[]), [1]);
+        assert_eq!(stuff_payload(&[0]), [1, 1]);
+        assert_eq!(stuff_payload(&[7, 255, 0, 254]), [4, 7, 1, 3, 254]);
+        assert_eq!(
+            unstuff_payload(&[4, 7, 1, 3, 254]).unwrap(),
+            [7, 255, 0, 254]
+        );
+
+        let mut payloads = vec![
+            vec![],
+            vec![0; 300],
+            vec![255; 300],
+            vec![1; MAX_RUN],
+            vec![1; MAX_RUN + 1],
+            (0..=255).cycle().take(1000).collect::<Vec<u8>>(),
+        ];
+        let mut run_then_zero = vec![1; MAX_RUN];
+        run_then_zero.push(0);
+        payloads.push(run_then_zero);
+
+        for payload in &payloads {
+            let stuffed = stuff_payload(payload);
+            assert!(stuffed.iter().all(|b| matches!(b, 1..=254)));
+            assert!(stuffed.len() <= max_stuffed_len(payload.len()));
+            assert_eq!(&unstuff_payload(&stuffed).unwrap(), payload);
+        }
+
+        // Worst case is runs without a 0 or 255
+        assert_eq!(
+            stuff_payload(&[1; 2 * MAX_RUN]).len(),
+            max_stuffed_len(2 * MAX_RUN)
+        );
+
+        assert_eq!(unstuff_payload(&[5, 1]), None);
+        assert_eq!(unstuff_payload(&[254]), None);
+        assert_eq!(unstuff_payload(&[0]), None);
+    }
+
+    #[test]
+    fn stuffed_len_limits_work() {
+        for space in 0..1000 {
+            let len = max_unstuffed_len(space);
+            assert!(len == 0 || max_stuffed_len(len) <= space);
+            assert!(max_stuffed_len(len + 1) > space);
+        }
+    }
+}
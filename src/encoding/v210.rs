@@ -0,0 +1,133 @@
+//! Lines of 10 bit 4:2:2 v210 video. Every 6 pixels are packed into 16 bytes, as
+//! four little endian words of three components each, in the order
+//! Cb Y Cr Y Cb Y Cr Y Cb Y Cr Y.
+
+use std::convert::TryInto;
+
+/// Luma of horizontal blanking
+pub const LUMA_BLANK: u16 = 0x040;
+
+/// Chroma of horizontal blanking
+pub const CHROMA_BLANK: u16 = 0x200;
+
+const PIXELS_PER_BLOCK: usize = 6;
+const BLOCK_SIZE: usize = 16;
+
+/// Bytes of a line `width` pixels wide, lines are padded to 48 pixels
+pub fn line_size(width: usize) -> usize {
+    (width + 47) / 48 * 128
+}
+
+/// Fills the first `width` pixels of `line` with `luma`, followed by blanking.
+/// Chroma is blanked.
+pub fn write_luma(line: &mut [u8], width: usize, luma: &[u16]) {
+    let blocks = (width + PIXELS_PER_BLOCK - 1) / PIXELS_PER_BLOCK;
+    for (i, block) in line[..blocks * BLOCK_SIZE]
+        .chunks_exact_mut(BLOCK_SIZE)
+        .enumerate()
+    {
+        let mut components = [CHROMA_BLANK; 2 * PIXELS_PER_BLOCK];
+        for pixel in 0..PIXELS_PER_BLOCK {
+            let x = i * PIXELS_PER_BLOCK + pixel;
+            components[2 * pixel + 1] = match luma.get(x) {
+                Some(&y) if x < width => y & 0x3ff,
+                _ => LUMA_BLANK,
+            };
+        }
+
+        for (word, components) in block.chunks_exact_mut(4).zip(components.chunks_exact(3)) {
+            let value =
+                components[0] as u32 | (components[1] as u32) << 10 | (components[2] as u32) << 20;
+            word.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Luma of the first `width` pixels of `line`
+pub fn read_luma(line: &[u8], width: usize) -> Vec<u16> {
+    let blocks = (width + PIXELS_PER_BLOCK - 1) / PIXELS_PER_BLOCK;
+    let mut luma = Vec::with_capacity(blocks * PIXELS_PER_BLOCK);
+    for block in line[..blocks * BLOCK_SIZE].chunks_exact(BLOCK_SIZE) {
+        let mut components = [0; 2 * PIXELS_PER_BLOCK];
+        for (word, components) in block.chunks_exact(4).zip(components.chunks_exact_mut(3)) {
+            let value = u32::from_le_bytes(word.try_into().unwrap());
+            components[0] = (value & 0x3ff) as u16;
+            components[1] = (value >> 10 & 0x3ff) as u16;
+            components[2] = (value >> 20 & 0x3ff) as u16;
+        }
+        luma.extend(components.iter().skip(1).step_by(2));
+    }
+    luma.truncate(width);
+
+    luma
+}
+
+/// Fills the luma of the first `width` pixels of `line` with `data`, a byte in the
+/// upper 8 bits of every sample, like `write_luma`. SDI only carries 10 bit samples
+/// and reserves 0x000-0x003 and 0x3fc-0x3ff, which bytes other than 0 and 255 keep
+/// clear of.
+pub fn write_bytes(line: &mut [u8], width: usize, data: &[u8]) {
+    let luma: Vec<u16> = data.iter().map(|byte| u16::from(*byte) << 2).collect();
+    write_luma(line, width, &luma);
+}
+
+/// Bytes written by `write_bytes` into the first `width` pixels of `line`, rounded
+/// to the nearest so that samples a little off still read right
+pub fn read_bytes(line: &[u8], width: usize) -> Vec<u8> {
+    read_luma(line, width)
+        .into_iter()
+        .map(|y| ((y + 2) >> 2).min(0xff) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_luma_works() {
+        assert_eq!(line_size(1920), 5120);
+        assert_eq!(line_size(1280), 3456);
+
+        let mut line = vec![0xaa; line_size(8)];
+        write_luma(&mut line, 8, &[0x3ff, 0x000, 0x155]);
+        // Cb Y Cr in the first word
+        assert_eq!(
+            line[..4],
+            (0x200u32 | 0x3ff << 10 | 0x200 << 20).to_le_bytes()
+        );
+        assert_eq!(
+            read_luma(&line, 8),
+            [0x3ff, 0x000, 0x155, 0x040, 0x040, 0x040, 0x040, 0x040]
+        );
+        // Only the blocks of `width` are written
+        assert_eq!(line[32..], [0xaa; 96][..]);
+
+        let luma: Vec<u16> = (0..1920).map(|x| (x % 1024) as u16).collect();
+        let mut line = vec![0; line_size(1920)];
+        write_luma(&mut line, 1920, &luma);
+        assert_eq!(read_luma(&line, 1920), luma);
+    }
+
+    #[test]
+    fn write_and_read_bytes_works() {
+        let data: Vec<u8> = (1..=254).collect();
+        let mut line = vec![0; line_size(300)];
+        write_bytes(&mut line, 300, &data);
+
+        let luma = read_luma(&line, 300);
+        assert!(luma.iter().all(|y| (0x004..0x3fc).contains(y)));
+        assert_eq!(luma[254..], [LUMA_BLANK; 46][..]);
+
+        let bytes = read_bytes(&line, 300);
+        assert_eq!(bytes[..254], data[..]);
+
+        // Small changes to the 10 bit samples on the way don't matter
+        let luma: Vec<u16> = luma.iter().map(|y| y + 1).collect();
+        write_luma(&mut line, 300, &luma);
+        assert_eq!(read_bytes(&line, 300), bytes);
+        let luma: Vec<u16> = luma.iter().map(|y| y - 3).collect();
+        write_luma(&mut line, 300, &luma);
+        assert_eq!(read_bytes(&line, 300), bytes);
+    }
+}
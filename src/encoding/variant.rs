@@ -0,0 +1,41 @@
+//! `GVariant`s are carried as their type string and a 0 byte, followed by the
+//! serialized data of their normal form, little endian
+
+/// Reverses `unpack_variant`
+pub fn pack_variant(type_string: &str, data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(type_string.len() + 1 + data.len());
+    output.extend_from_slice(type_string.as_bytes());
+    output.push(0);
+    output.extend_from_slice(data);
+    output
+}
+
+/// Type string and data of a packed variant, `None` if there is no type string.
+/// Neither of them is checked.
+pub fn unpack_variant(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let type_string = std::str::from_utf8(&data[..end]).ok()?;
+    if type_string.is_empty() {
+        return None;
+    }
+    Some((type_string, &data[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_and_unpack_variant_works() {
+        // (u, s) of 42 and "hi"
+        let data = [42, 0, 0, 0, b'h', b'i', 0];
+        let packed = pack_variant("(us)", &data);
+        assert_eq!(packed[..5], *b"(us)\0");
+        assert_eq!(unpack_variant(&packed), Some(("(us)", &data[..])));
+
+        assert_eq!(unpack_variant(b"u\0"), Some(("u", &[][..])));
+        assert_eq!(unpack_variant(b"u"), None);
+        assert_eq!(unpack_variant(b"\0\x01"), None);
+        assert_eq!(unpack_variant(b"\xff\0"), None);
+    }
+}
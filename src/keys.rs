@@ -0,0 +1,22 @@
+use std::fs;
+
+use crate::encoding::Keys;
+
+/// Keys from the `key` property followed by the ones in `key_file`, if set
+pub fn load_keys(key: &str, key_file: Option<&str>) -> Result<Keys, gst::ErrorMessage> {
+    let mut keys = key.to_string();
+
+    if let Some(key_file) = key_file {
+        let contents = fs::read_to_string(key_file).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to read key file {}: {}", key_file, err]
+            )
+        })?;
+        keys.push('\n');
+        keys.push_str(&contents);
+    }
+
+    keys.parse()
+        .map_err(|err| gst::error_msg!(gst::ResourceError::Settings, ["{}", err]))
+}
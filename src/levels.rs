@@ -0,0 +1,87 @@
+//! Samples of `encoding::levels` in the pixels of 8 bit video formats, as grey
+//! written into every colour component
+
+use crate::encoding::levels;
+
+/// Byte of component `component` of pixel `x`, `y`
+fn component_offset(info: &gst_video::VideoInfo, component: usize, x: u32, y: u32) -> usize {
+    let format_info = info.format_info();
+    let plane = format_info.plane()[component] as usize;
+
+    info.offset()[plane]
+        + (y >> format_info.h_sub()[component]) as usize * info.stride()[plane] as usize
+        + (x >> format_info.w_sub()[component]) as usize
+            * format_info.pixel_stride()[component] as usize
+        + format_info.poffset()[component] as usize
+}
+
+/// Whether frames of `info` can hold levels
+pub fn supports(info: &gst_video::VideoInfo) -> bool {
+    let format_info = info.format_info();
+    format_info.bits() == 8 && format_info.pixel_stride()[0] > 0
+}
+
+/// Pixels of a frame of `info`
+pub fn pixels(info: &gst_video::VideoInfo) -> usize {
+    info.width() as usize * info.height() as usize
+}
+
+/// Writes `data` with `levels::write_levels` into `frame`, pixel by pixel and row by
+/// row. RGB gets the same value in every component, YUV gets neutral chroma.
+pub fn write_levels(info: &gst_video::VideoInfo, data: &[u8], frame: &mut [u8]) {
+    let mut samples = vec![0; pixels(info)];
+    levels::write_levels(data, &mut samples);
+
+    let format_info = info.format_info();
+    let is_rgb = format_info.is_rgb();
+    let alpha = format_info
+        .has_alpha()
+        .then(|| format_info.n_components() as usize - 1);
+    let width = info.width();
+
+    for (i, &sample) in samples.iter().enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        for component in 0..format_info.n_components() as usize {
+            let value = if Some(component) == alpha {
+                0xff
+            } else if is_rgb || component == 0 {
+                sample
+            } else {
+                0x80
+            };
+            frame[component_offset(info, component, x, y)] = value;
+        }
+    }
+}
+
+/// Data of a frame written by `write_levels`, `None` if it doesn't start with a
+/// training sequence. RGB is read from every colour component, YUV from luma.
+pub fn read_levels(info: &gst_video::VideoInfo, frame: &[u8]) -> Option<Vec<u8>> {
+    let format_info = info.format_info();
+    let components = if format_info.is_rgb() { 3 } else { 1 };
+    let width = info.width();
+
+    let read = |component, pixels| -> Vec<u8> {
+        (0..pixels)
+            .map(|i| frame[component_offset(info, component, i as u32 % width, i as u32 / width)])
+            .collect()
+    };
+
+    // The training sequence is checked before reading the whole frame
+    let mut calibrations = Vec::with_capacity(components);
+    for component in 0..components {
+        let training = read(component, levels::TRAINING_SIZE.min(pixels(info)));
+        calibrations.push(levels::Calibration::train(&training)?);
+    }
+
+    let channels: Vec<Vec<u8>> = (0..components)
+        .map(|component| read(component, pixels(info)))
+        .collect();
+    let channels: Vec<(&[u8], levels::Calibration)> = channels
+        .iter()
+        .map(Vec::as_slice)
+        .zip(calibrations)
+        .collect();
+
+    Some(levels::read_levels(&channels))
+}
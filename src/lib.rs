@@ -1,15 +1,37 @@
+#[cfg(feature = "plugin")]
 use gst::glib;
 
+#[cfg(feature = "plugin")]
+mod captions;
+#[cfg(feature = "plugin")]
+mod dataimpair;
+#[cfg(feature = "plugin")]
 mod datasink;
+#[cfg(feature = "plugin")]
 mod datasrc;
-mod encoding;
+pub mod encoding;
+#[cfg(feature = "plugin")]
+mod keys;
+#[cfg(feature = "plugin")]
+mod levels;
+#[cfg(feature = "plugin")]
+mod lines;
+#[cfg(feature = "plugin")]
+mod mpegts;
+#[cfg(feature = "plugin")]
+mod timecode;
+#[cfg(feature = "plugin")]
+mod variant;
 
+#[cfg(feature = "plugin")]
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    dataimpair::register(plugin)?;
     datasink::register(plugin)?;
     datasrc::register(plugin)?;
     Ok(())
 }
 
+#[cfg(feature = "plugin")]
 gst::plugin_define!(
     gstdatavideo,
     env!("CARGO_PKG_DESCRIPTION"),
@@ -0,0 +1,92 @@
+//! Frames written line by line into the first plane, with a margin of pixels left
+//! out at the right end of every line. A picture shifted right by up to that many
+//! pixels still holds every line whole, so `datasink` can read it back from where
+//! it finds it. The `FrameLayout` does the work, this only lays it out in pixels.
+//!
+//! v210 pictures carry frames in their luma samples instead, a byte per pixel, see
+//! `write_v210`. Their layouts are of that plane of bytes.
+
+use crate::encoding::{v210, FrameLayout};
+
+/// Byte of a line of the first plane pixel `column` starts at, `None` if it
+/// doesn't start on a byte of its own
+pub fn column_offset(info: &gst_video::VideoInfo, column: u32) -> Option<usize> {
+    if info.format() == gst_video::VideoFormat::V210 {
+        return Some(column as usize);
+    }
+
+    let pixel_stride = info.format_info().pixel_stride()[0] as usize;
+    (pixel_stride > 0).then(|| column as usize * pixel_stride)
+}
+
+/// Bytes from the start of a line of the first plane to the start of the next
+pub fn stride(info: &gst_video::VideoInfo) -> usize {
+    if info.format() == gst_video::VideoFormat::V210 {
+        return info.width() as usize;
+    }
+
+    info.stride()[0] as usize
+}
+
+/// Bytes of frame data in every line, up to the last pixel with an offset of its
+/// own before the `margin` pixels at the right end
+fn line_bytes(info: &gst_video::VideoInfo, margin: u32) -> usize {
+    (0..=info.width().saturating_sub(margin))
+        .rev()
+        .find_map(|column| column_offset(info, column))
+        .unwrap_or(0)
+}
+
+/// Layout of frames in the first plane of pictures of `info`, with `margin`
+/// pixels left out at the right end of every line
+pub fn layout(info: &gst_video::VideoInfo, margin: u32) -> FrameLayout {
+    let line_width = line_bytes(info, 0);
+    let offset = match info.format() {
+        gst_video::VideoFormat::V210 => 0,
+        _ => info.offset()[0],
+    };
+    FrameLayout::with_lines(offset, stride(info), line_width, info.height() as usize)
+        .with_margin(line_width - line_bytes(info, margin))
+}
+
+/// `layout` of a picture that was shifted down by `line` lines and right by
+/// `column` pixels since, `None` if `column` doesn't start on a byte of its own
+pub fn shifted_layout(
+    info: &gst_video::VideoInfo,
+    margin: u32,
+    line: u32,
+    column: u32,
+) -> Option<FrameLayout> {
+    let offset = column_offset(info, column)?;
+    Some(layout(info, margin).shifted(line as usize, offset))
+}
+
+/// Plane of blank luma bytes of a v210 picture of `info`, to lay frames out in
+pub fn blank_v210(info: &gst_video::VideoInfo) -> Vec<u8> {
+    let blank = (v210::LUMA_BLANK >> 2) as u8;
+    vec![blank; info.width() as usize * info.height() as usize]
+}
+
+/// Writes the plane of luma bytes `luma` into the v210 picture `frame` of `info`,
+/// a byte in the upper 8 bits of every luma sample, with chroma blanked. The
+/// picture as it is wouldn't make it through SDI, which only carries 10 bits of
+/// every sample and reserves the lowest and highest values.
+pub fn write_v210(info: &gst_video::VideoInfo, luma: &[u8], frame: &mut [u8]) {
+    let width = info.width() as usize;
+    for (line, luma) in frame[info.offset()[0]..]
+        .chunks_mut(info.stride()[0] as usize)
+        .zip(luma.chunks(width))
+    {
+        v210::write_bytes(line, width, luma);
+    }
+}
+
+/// Plane of luma bytes written by `write_v210`
+pub fn read_v210(info: &gst_video::VideoInfo, frame: &[u8]) -> Vec<u8> {
+    let width = info.width() as usize;
+    frame[info.offset()[0]..]
+        .chunks_exact(info.stride()[0] as usize)
+        .take(info.height() as usize)
+        .flat_map(|line| v210::read_bytes(line, width))
+        .collect()
+}
@@ -0,0 +1,93 @@
+//! The few functions of libgstmpegts needed to pass SCTE-35 sections to and from
+//! `tsdemux` and `mpegtsmux`, gstreamer-rs has no bindings for it
+
+use std::ptr;
+
+use gst::glib;
+use once_cell::sync::Lazy;
+
+use glib::translate::from_glib_full;
+
+#[repr(C)]
+struct GstMpegtsSection {
+    _private: [u8; 0],
+}
+
+#[link(name = "gstmpegts-1.0")]
+extern "C" {
+    fn gst_mpegts_initialize();
+    fn gst_mpegts_section_new(pid: u16, data: *mut u8, data_size: usize) -> *mut GstMpegtsSection;
+    fn gst_mpegts_section_packetize(
+        section: *mut GstMpegtsSection,
+        output_size: *mut usize,
+    ) -> *mut u8;
+    fn gst_event_parse_mpegts_section(event: *mut gst::ffi::GstEvent) -> *mut GstMpegtsSection;
+    fn gst_event_new_mpegts_section(section: *mut GstMpegtsSection) -> *mut gst::ffi::GstEvent;
+}
+
+/// Structure name of events with a SCTE-35 section, see `gst_mpegts_section_send_event`
+const SCTE_SIT_EVENT: &str = "scte-sit";
+
+// mpegtsmux writes sections on its own `scte-35-pid`
+const SCTE_35_PID: u16 = 0;
+
+static INITIALIZED: Lazy<()> = Lazy::new(|| unsafe { gst_mpegts_initialize() });
+
+fn section_unref(section: *mut GstMpegtsSection) {
+    unsafe { gst::ffi::gst_mini_object_unref(section as *mut gst::ffi::GstMiniObject) }
+}
+
+/// The SCTE-35 section of `event`, `None` for other events
+pub fn parse_scte35_event(event: &gst::Event) -> Option<Vec<u8>> {
+    let is_scte35 = event.type_() == gst::EventType::CustomDownstream
+        && event
+            .structure()
+            .map_or(false, |structure| structure.name() == SCTE_SIT_EVENT);
+    if !is_scte35 {
+        return None;
+    }
+
+    Lazy::force(&INITIALIZED);
+    unsafe {
+        let section = gst_event_parse_mpegts_section(event.as_ptr() as *mut _);
+        if section.is_null() {
+            return None;
+        }
+
+        // Owned by the section
+        let mut size = 0;
+        let data = gst_mpegts_section_packetize(section, &mut size);
+        let bytes = if data.is_null() {
+            None
+        } else {
+            Some(std::slice::from_raw_parts(data, size).to_vec())
+        };
+        section_unref(section);
+
+        bytes
+    }
+}
+
+/// Event with a SCTE-35 `section` for `mpegtsmux`, `None` if libgstmpegts can't
+/// parse it
+pub fn new_scte35_event(section: &[u8]) -> Option<gst::Event> {
+    Lazy::force(&INITIALIZED);
+    unsafe {
+        // The section takes ownership of the data
+        let data = glib::ffi::g_malloc(section.len()) as *mut u8;
+        ptr::copy_nonoverlapping(section.as_ptr(), data, section.len());
+
+        let section = gst_mpegts_section_new(SCTE_35_PID, data, section.len());
+        if section.is_null() {
+            return None;
+        }
+        let event = gst_event_new_mpegts_section(section);
+        section_unref(section);
+
+        if event.is_null() {
+            None
+        } else {
+            Some(from_glib_full(event))
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::convert::TryFrom;
+
+use crate::encoding::Timecode;
+
+/// Timecode flags for frames at `fps`, NTSC rates use drop frame timecodes
+pub fn timecode_flags(fps: gst::Fraction) -> gst_video::VideoTimeCodeFlags {
+    if fps == gst::Fraction::new(30000, 1001) || fps == gst::Fraction::new(60000, 1001) {
+        gst_video::VideoTimeCodeFlags::DROP_FRAME
+    } else {
+        gst_video::VideoTimeCodeFlags::empty()
+    }
+}
+
+/// `None` if `timecode` is not valid at `fps`
+pub fn to_video_time_code(
+    timecode: Timecode,
+    fps: gst::Fraction,
+) -> Option<gst_video::ValidVideoTimeCode> {
+    if fps == gst::Fraction::new(0, 1) {
+        return None;
+    }
+
+    let timecode = gst_video::VideoTimeCode::new(
+        fps,
+        None,
+        timecode_flags(fps),
+        timecode.hours,
+        timecode.minutes,
+        timecode.seconds,
+        timecode.frames,
+        0,
+    );
+
+    gst_video::ValidVideoTimeCode::try_from(timecode).ok()
+}
+
+pub fn from_video_time_code(timecode: &gst_video::ValidVideoTimeCode) -> Timecode {
+    Timecode {
+        hours: timecode.hours(),
+        minutes: timecode.minutes(),
+        seconds: timecode.seconds(),
+        frames: timecode.frames(),
+    }
+}
@@ -0,0 +1,100 @@
+//! `GVariant`s as `encoding::variant` packs them
+
+use std::ffi::{CStr, CString};
+
+use gst::glib;
+
+use glib::translate::{from_glib_full, from_glib_none, ToGlibPtr};
+
+use crate::encoding::variant::{pack_variant, unpack_variant};
+
+/// `variant` with its type, in its normal form and little endian whatever the host
+pub fn variant_to_bytes(variant: &glib::Variant) -> Vec<u8> {
+    unsafe {
+        let mut normal: glib::Variant = from_glib_full(glib::ffi::g_variant_get_normal_form(
+            variant.to_glib_none().0,
+        ));
+        if cfg!(target_endian = "big") {
+            normal = from_glib_full(glib::ffi::g_variant_byteswap(normal.to_glib_none().0));
+        }
+        let ptr: *mut glib::ffi::GVariant = normal.to_glib_none().0;
+
+        let type_string = CStr::from_ptr(glib::ffi::g_variant_get_type_string(ptr));
+        let size = glib::ffi::g_variant_get_size(ptr);
+        let data = if size == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(glib::ffi::g_variant_get_data(ptr) as *const u8, size)
+        };
+
+        pack_variant(&type_string.to_string_lossy(), data)
+    }
+}
+
+/// Reverses `variant_to_bytes`, `None` if the type is invalid or not definite.
+/// Data that doesn't match the type gives the default value of the type, as with
+/// any untrusted `GVariant` data.
+pub fn variant_from_bytes(data: &[u8]) -> Option<glib::Variant> {
+    let (type_string, data) = unpack_variant(data)?;
+    let type_string = CString::new(type_string).ok()?;
+
+    unsafe {
+        if glib::ffi::g_variant_type_string_is_valid(type_string.as_ptr()) == glib::ffi::GFALSE {
+            return None;
+        }
+
+        let variant_type = glib::ffi::g_variant_type_new(type_string.as_ptr());
+        if glib::ffi::g_variant_type_is_definite(variant_type) == glib::ffi::GFALSE {
+            glib::ffi::g_variant_type_free(variant_type);
+            return None;
+        }
+
+        let bytes = glib::Bytes::from(data);
+        let variant = glib::ffi::g_variant_new_from_bytes(
+            variant_type,
+            bytes.to_glib_none().0,
+            glib::ffi::GFALSE,
+        );
+        glib::ffi::g_variant_type_free(variant_type);
+
+        // The new variant is floating, which `from_glib_none` sinks instead of adding
+        // a reference
+        let variant: glib::Variant = from_glib_none(variant);
+        if cfg!(target_endian = "big") {
+            return Some(from_glib_full(glib::ffi::g_variant_byteswap(
+                variant.to_glib_none().0,
+            )));
+        }
+
+        Some(variant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glib::ToVariant;
+
+    #[test]
+    fn variant_round_trip_works() {
+        let variant = (42i32, "Hello".to_string(), 1.5f64).to_variant();
+        let decoded = variant_from_bytes(&variant_to_bytes(&variant)).unwrap();
+        assert_eq!(decoded, variant);
+        assert_eq!(decoded.type_().to_str(), "(isd)");
+
+        // Sunk, so the only reference is ours and dropping it frees it
+        unsafe {
+            let ptr: *mut glib::ffi::GVariant = decoded.to_glib_none().0;
+            assert_eq!(glib::ffi::g_variant_is_floating(ptr), glib::ffi::GFALSE);
+        }
+
+        // Little endian on any host
+        let bytes = variant_to_bytes(&42i32.to_variant());
+        assert_eq!(unpack_variant(&bytes), Some(("i", &[42, 0, 0, 0][..])));
+
+        // Not a type, or not a definite one
+        assert_eq!(variant_from_bytes(&pack_variant("(i", &[])), None);
+        assert_eq!(variant_from_bytes(&pack_variant("a*", &[])), None);
+    }
+}